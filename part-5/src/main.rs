@@ -132,6 +132,12 @@ impl<'a> Lexer<'a> {
     }
 }
 
+// a small tree built only for :explain, since the interpreter evaluates while it parses
+enum Expr {
+    NUM(i32),
+    BINOP(Box<Expr>, CalcTokenType, Box<Expr>),
+}
+
 struct Interpreter<'a> {
     lexer: Lexer<'a>,
     current_token: Option<Token>,
@@ -216,6 +222,95 @@ impl<'a> Interpreter<'a> {
 
         return Ok(result);
     }
+
+    fn factor_tree(&mut self) -> Result<Expr, char> {
+        let token = self.current_token.unwrap();
+        let _ = self.eat(CalcTokenType::INTEGER);
+
+        match token.value {
+            Some(Value::INT(x)) => Ok(Expr::NUM(x)),
+            Some(Value::CHAR(y)) => Err(y),
+            None => Err('F'),
+        }
+    }
+
+    fn term_tree(&mut self) -> Result<Expr, char> {
+        let mut node = self.factor_tree()?;
+        let action = vec![CalcTokenType::MUL, CalcTokenType::DIV];
+        while action.contains(&self.current_token.unwrap().genre) {
+            let op = self.current_token.unwrap().genre;
+            let _ = self.eat(op);
+            node = Expr::BINOP(Box::new(node), op, Box::new(self.factor_tree()?));
+        }
+
+        return Ok(node);
+    }
+
+    fn expr_tree(&mut self) -> Result<Expr, char> {
+        let mut node = self.term_tree()?;
+        let action = vec![CalcTokenType::PLUS, CalcTokenType::MINUS];
+        while action.contains(&self.current_token.unwrap().genre) {
+            let op = self.current_token.unwrap().genre;
+            let _ = self.eat(op);
+            node = Expr::BINOP(Box::new(node), op, Box::new(self.term_tree()?));
+        }
+
+        return Ok(node);
+    }
+
+    fn show(&self, node: &Expr, precedence: usize) -> String {
+        match node {
+            Expr::NUM(x) => x.to_string(),
+            Expr::BINOP(left, op, right) => {
+                let (own, symbol) = match op {
+                    CalcTokenType::PLUS => (1, "+"),
+                    CalcTokenType::MINUS => (1, "-"),
+                    CalcTokenType::MUL => (2, "*"),
+                    _ => (2, "/"),
+                };
+                let text = format!("{} {} {}", self.show(left, own), symbol, self.show(right, own + 1));
+                if own < precedence {
+                    format!("({})", text)
+                } else {
+                    text
+                }
+            },
+        }
+    }
+
+    fn operate(&self, op: CalcTokenType, left: i32, right: i32) -> Result<i32, String> {
+        let result = match op {
+            CalcTokenType::PLUS => left.checked_add(right),
+            CalcTokenType::MINUS => left.checked_sub(right),
+            CalcTokenType::MUL => left.checked_mul(right),
+            _ if right == 0 => return Err(String::from("Division by zero")),
+            _ => left.checked_div(right),
+        };
+        result.ok_or(String::from("Integer overflow"))
+    }
+
+    // evaluates the leftmost operator whose operands are both numbers and keeps the rest of the tree
+    fn reduce(&self, node: Expr) -> Result<Expr, String> {
+        match node {
+            Expr::BINOP(left, op, right) => match (*left, *right) {
+                (Expr::NUM(x), Expr::NUM(y)) => Ok(Expr::NUM(self.operate(op, x, y)?)),
+                (Expr::NUM(x), right) => Ok(Expr::BINOP(Box::new(Expr::NUM(x)), op, Box::new(self.reduce(right)?))),
+                (left, right) => Ok(Expr::BINOP(Box::new(self.reduce(left)?), op, Box::new(right))),
+            },
+            Expr::NUM(x) => Ok(Expr::NUM(x)),
+        }
+    }
+
+    fn explain(&mut self) -> Result<Vec<String>, String> {
+        self.current_token = Some(self.lexer.get_next_token());
+        let mut tree = self.expr_tree().map_err(|e| format!("{e:?}"))?;
+        let mut steps = vec![self.show(&tree, 0)];
+        while !matches!(tree, Expr::NUM(_)) {
+            tree = self.reduce(tree)?;
+            steps.push(self.show(&tree, 0));
+        }
+        return Ok(steps);
+    }
 }
 
 
@@ -228,6 +323,15 @@ fn main() -> io::Result<()>{
         io::stdin().read_line(&mut input)?;
 
         let user_input = input.trim().to_string();
+        if let Some(text) = user_input.strip_prefix(":explain ") {
+            let text = text.to_string();
+            let mut interpreter = Interpreter::new(Lexer::new(&text));
+            match interpreter.explain() {
+                Ok(steps) => println!("{}", steps.join(" => ")),
+                Err(e) => println!("Error when explain expression: {e}"),
+            }
+            continue;
+        }
         let lexer = Lexer::new(&user_input);
         let mut interpreter = Interpreter::new(lexer);
         let result = interpreter.expr();
@@ -240,3 +344,26 @@ fn main() -> io::Result<()>{
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn explain(text: &str) -> Result<Vec<String>, String> {
+        let text = text.to_string();
+        Interpreter::new(Lexer::new(&text)).explain()
+    }
+
+    #[test]
+    fn explain_steps() {
+        assert_eq!(explain("2 + 3 * 4 - 6 / 2").unwrap(), ["2 + 3 * 4 - 6 / 2", "2 + 12 - 6 / 2", "14 - 6 / 2", "14 - 3", "11"]);
+        assert_eq!(explain("7").unwrap(), ["7"]);
+    }
+
+    #[test]
+    fn explain_errors() {
+        assert_eq!(explain("8 / 2 / 0"), Err(String::from("Division by zero")));
+        assert_eq!(explain("2147483647 + 1"), Err(String::from("Integer overflow")));
+        assert_eq!(explain("2 + * 3"), Err(String::from("'*'")));
+    }
+}
@@ -236,9 +236,9 @@ impl<'a> Parser<'a> {
             return Ok(AST::NUM(Num::new(token.unwrap())));
         } else if token_type == &CalcTokenType::LPAREN {
             let _ = self.eat(CalcTokenType::LPAREN);
-            let node = self.expr();
-            let _ = self.eat(CalcTokenType::RPAREN);
-            return node;
+            let node = self.expr()?;
+            self.eat(CalcTokenType::RPAREN).map_err(|_| ')')?;
+            return Ok(node);
         }
 
         return Err('F')
@@ -335,6 +335,66 @@ impl<'a> Interpreter<'a> {
         let tree = self.parser.parse()?;
         return Ok(self.visit(Box::new(tree)));
     }
+
+    fn show(&self, node: &AST, precedence: usize) -> String {
+        match node {
+            AST::NUM(x) => match x.value {
+                Some(Value::INT(y)) => y.to_string(),
+                _ => String::new(),
+            },
+            AST::BINOP(x) => {
+                let (own, symbol) = match x.op.genre {
+                    CalcTokenType::PLUS => (1, "+"),
+                    CalcTokenType::MINUS => (1, "-"),
+                    CalcTokenType::MUL => (2, "*"),
+                    _ => (2, "/"),
+                };
+                let text = format!("{} {} {}", self.show(&x.left, own), symbol, self.show(&x.right, own + 1));
+                if own < precedence {
+                    format!("({})", text)
+                } else {
+                    text
+                }
+            },
+        }
+    }
+
+    // the operands are already numbers, so only the arithmetic itself can go wrong
+    fn operate(&self, op: CalcTokenType, left: i32, right: i32) -> Result<i32, String> {
+        let result = match op {
+            CalcTokenType::PLUS => left.checked_add(right),
+            CalcTokenType::MINUS => left.checked_sub(right),
+            CalcTokenType::MUL => left.checked_mul(right),
+            _ if right == 0 => return Err(String::from("Division by zero")),
+            _ => left.checked_div(right),
+        };
+        result.ok_or(String::from("Integer overflow"))
+    }
+
+    // evaluates the leftmost operator whose operands are both numbers and keeps the rest of the tree
+    fn reduce(&self, node: AST) -> Result<AST, String> {
+        match node {
+            AST::BINOP(x) => match (&*x.left, &*x.right) {
+                (AST::NUM(Num { value: Some(Value::INT(left)), .. }), AST::NUM(Num { value: Some(Value::INT(right)), .. })) => {
+                    let value = self.operate(x.op.genre, *left, *right)?;
+                    Ok(AST::NUM(Num::new(Token { genre: CalcTokenType::INTEGER, value: Some(Value::INT(value)) })))
+                },
+                (AST::NUM(_), _) => Ok(AST::BINOP(BinOp { right: Box::new(self.reduce(*x.right)?), ..x })),
+                _ => Ok(AST::BINOP(BinOp { left: Box::new(self.reduce(*x.left)?), ..x })),
+            },
+            AST::NUM(x) => Ok(AST::NUM(x)),
+        }
+    }
+
+    fn explain(&mut self) -> Result<Vec<String>, String> {
+        let mut tree = self.parser.parse().map_err(|e| format!("{e:?}"))?;
+        let mut steps = vec![self.show(&tree, 0)];
+        while !matches!(tree, AST::NUM(_)) {
+            tree = self.reduce(tree)?;
+            steps.push(self.show(&tree, 0));
+        }
+        return Ok(steps);
+    }
 }
 
 fn main() -> io::Result<()>{
@@ -346,6 +406,15 @@ fn main() -> io::Result<()>{
         io::stdin().read_line(&mut input)?;
 
         let user_input = input.trim().to_string();
+        if let Some(text) = user_input.strip_prefix(":explain ") {
+            let text = text.to_string();
+            let mut interpreter = Interpreter::new(Parser::new(Lexer::new(&text)));
+            match interpreter.explain() {
+                Ok(steps) => println!("{}", steps.join(" => ")),
+                Err(e) => println!("Error when explain expression: {e}"),
+            }
+            continue;
+        }
         let lexer = Lexer::new(&user_input);
         let parser = Parser::new(lexer);
         let mut interpreter = Interpreter::new(parser);
@@ -359,3 +428,26 @@ fn main() -> io::Result<()>{
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn explain(text: &str) -> Result<Vec<String>, String> {
+        let text = text.to_string();
+        Interpreter::new(Parser::new(Lexer::new(&text))).explain()
+    }
+
+    #[test]
+    fn explain_steps() {
+        assert_eq!(explain("(1 + 2) * 3 - 4 / 2").unwrap(), ["(1 + 2) * 3 - 4 / 2", "3 * 3 - 4 / 2", "9 - 4 / 2", "9 - 2", "7"]);
+        assert_eq!(explain("10 - (2 - 3)").unwrap(), ["10 - (2 - 3)", "10 - -1", "11"]);
+    }
+
+    #[test]
+    fn explain_errors() {
+        assert_eq!(explain("7 / (2 - 2)"), Err(String::from("Division by zero")));
+        assert_eq!(explain("2 + (3"), Err(String::from("')'")));
+        assert_eq!(explain("2 + * 3"), Err(String::from("'F'")));
+    }
+}
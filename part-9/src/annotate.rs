@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use super::visit::{VisitResult, Visitor};
+use super::{
+    AST, ASTArena, Assign, BinOp, CalcTokenType, Compound, NodeId, Num, OverflowMode, Program, ScopedSymbolTable, UnaryOp,
+    Value, Var,
+};
+
+struct SourceToSourceCompiler {
+    current_scope: ScopedSymbolTable,
+    types: HashMap<String, &'static str>,
+    indent: usize,
+    // the binding power of the operator around the expression being visited
+    precedence: usize,
+}
+
+// the annotated text of a node and the type it evaluates to
+impl VisitResult for (String, &'static str) {
+    fn output() -> Self {
+        (String::new(), "UNKNOWN")
+    }
+
+    fn branch(&self) -> bool {
+        false
+    }
+}
+
+impl SourceToSourceCompiler {
+    fn new() -> SourceToSourceCompiler {
+        SourceToSourceCompiler {
+            current_scope: ScopedSymbolTable::new("global", 1, None),
+            types: HashMap::new(),
+            indent: 0,
+            precedence: 0,
+        }
+    }
+
+    fn compile(mut self, program: &Program) -> String {
+        let (body, _) = self.visit(&program.ast, program.root);
+        let scope_name = self.current_scope.scope_name.clone();
+        let level = self.current_scope.scope_level;
+
+        let mut output = String::new();
+        if program.overflow == Some(OverflowMode::WRAP) {
+            output += "{$Q-}\n";
+        } else if program.overflow == Some(OverflowMode::TRAP) {
+            output += "{$Q+}\n";
+        }
+        output += &format!("{{ program main{} }}\n", level - 1);
+        output += &format!("{{ scope {}, level {} }}\n", scope_name, level);
+        for name in self.current_scope.slot_names() {
+            let genre = self.types.get(&name).copied().unwrap_or("UNKNOWN");
+            output += &format!("{{ var {}{} : {}; }}\n", name, level, genre);
+        }
+        output += &format!("{}. {{END OF main}}\n", body);
+        output
+    }
+
+    fn expression(&mut self, ast: &ASTArena, id: NodeId, precedence: usize) -> (String, &'static str) {
+        self.precedence = precedence;
+        self.visit(ast, id)
+    }
+}
+
+impl Visitor for SourceToSourceCompiler {
+    type Result = (String, &'static str);
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) -> (String, &'static str) {
+        let precedence = self.precedence;
+        let own = match node.op.genre {
+            CalcTokenType::PLUS | CalcTokenType::MINUS => 1,
+            _ => 2,
+        };
+        let (left, left_type) = self.expression(ast, node.left, own);
+        let (right, right_type) = self.expression(ast, node.right, own + 1);
+        let genre = match (left_type, right_type) {
+            ("INTEGER", "INTEGER") => "INTEGER",
+            ("REAL", "INTEGER" | "REAL") | ("INTEGER", "REAL") => "REAL",
+            _ => "UNKNOWN",
+        };
+        let symbol = node.op.value.clone().unwrap_or(Value::UNIT);
+        let text = format!("{} {} {}", left, symbol, right);
+        if own < precedence {
+            (format!("({})", text), genre)
+        } else {
+            (text, genre)
+        }
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) -> (String, &'static str) {
+        let value = node.token.value.clone().unwrap_or(Value::UNIT);
+        (value.to_string(), value.type_name())
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) -> (String, &'static str) {
+        let (operand, genre) = self.expression(ast, node.expr, 3);
+        let symbol = node.op.value.clone().unwrap_or(Value::UNIT);
+        (format!("{}{}", symbol, operand), genre)
+    }
+
+    fn visit_compound(&mut self, ast: &ASTArena, _id: NodeId, node: &Compound) -> (String, &'static str) {
+        let padding = "    ".repeat(self.indent);
+        self.indent += 1;
+        let children: Vec<String> = node.children.iter().map(|child| self.visit(ast, *child).0).collect();
+        self.indent -= 1;
+        (format!("{}BEGIN\n{}\n{}END", padding, children.join(";\n"), padding), "UNKNOWN")
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) -> (String, &'static str) {
+        let padding = "    ".repeat(self.indent);
+        let (right, genre) = self.expression(ast, node.right, 0);
+        if let AST::VAR(var) = &ast[node.left] {
+            self.types.entry(var.name()).or_insert(genre);
+        }
+        let (left, _) = self.expression(ast, node.left, 0);
+        (format!("{}{} := {}", padding, left, right), "UNKNOWN")
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, _id: NodeId, node: &Var) -> (String, &'static str) {
+        let name = node.name();
+        let (depth, symbol) = match self.current_scope.lookup(&name, false) {
+            Some((depth, symbol)) => (depth, symbol.name.clone()),
+            None => (0, self.current_scope.insert(&name).name.clone()),
+        };
+        let level = self.current_scope.enclosing(depth).scope_level;
+        let genre = self.types.get(&symbol).copied().unwrap_or("UNKNOWN");
+        (format!("{}{} {{:{}}}", symbol, level, genre), genre)
+    }
+}
+
+pub fn annotate(program: &Program) -> String {
+    SourceToSourceCompiler::new().compile(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::annotate;
+    use crate::tests::output;
+    use crate::{Options, OverflowMode, Program};
+
+    const TEXT: &str = "{$Q-}\nBEGIN a := 2; b := (a + 1) * -a; BEGIN c := b / 2 - (a - b) END END.";
+
+    fn annotated(text: &str) -> String {
+        annotate(&Program::parse(&text.to_string()).unwrap_or_else(|e| panic!("{}", e)))
+    }
+
+    #[test]
+    fn names_and_types_are_annotated() {
+        assert_eq!(annotated(TEXT), "\
+{$Q-}
+{ program main0 }
+{ scope global, level 1 }
+{ var a1 : INTEGER; }
+{ var b1 : INTEGER; }
+{ var c1 : INTEGER; }
+BEGIN
+    a1 {:INTEGER} := 2;
+    b1 {:INTEGER} := (a1 {:INTEGER} + 1) * -a1 {:INTEGER};
+    BEGIN
+        c1 {:INTEGER} := b1 {:INTEGER} / 2 - (a1 {:INTEGER} - b1 {:INTEGER})
+    END
+END. {END OF main}
+");
+    }
+
+    #[test]
+    fn annotated_program_parses_and_runs_the_same() {
+        let annotated = annotated(TEXT);
+        let program = Program::parse(&annotated).unwrap_or_else(|e| panic!("{}\n{}", e, annotated));
+        // the {$Q-} directive survives, so the annotated program overflows the same way
+        assert_eq!(program.overflow, Some(OverflowMode::WRAP));
+        assert_eq!(output(&annotated, &Options::new()), "a1: 2\nb1: -6\nc1: -11\n");
+        assert_eq!(output(TEXT, &Options::new()), "a: 2\nb: -6\nc: -11\n");
+    }
+}
@@ -0,0 +1,773 @@
+use std::{fmt::Display, io};
+
+use num_bigint::BigInt;
+
+use super::{
+    AST, ASTArena, Arithmetic, Assign, BinOp, CalcTokenType, Compound, ErrorCode, IntegerWidth, InterpretError, NoOp,
+    NodeId, Num, OverflowMode, Program, Runtime, RuntimeError, SideTable, Span, UnaryOp, Value, Var,
+    ir::{Function, Instr, Operand},
+    visit::{Visitor, walk_binop, walk_compound, walk_unaryop},
+};
+
+const MAGIC: &[u8; 4] = b"LBC\0";
+const VERSION: u16 = 1;
+
+// there is no JUMP, CALL or RET yet: the language has no control flow or procedures to compile them from,
+// so they come with the statements that need them
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Op {
+    PUSH(u32),
+    LOAD(u32, u32),
+    STORE(u32, u32),
+    GET(u32),
+    SET(u32),
+    ADD,
+    SUB,
+    MUL,
+    DIV,
+    NEG,
+    POS,
+    STEP,
+    HALT,
+}
+
+impl Op {
+    fn opcode(&self) -> u8 {
+        match self {
+            Op::PUSH(_) => 0x01,
+            Op::LOAD(..) => 0x02,
+            Op::STORE(..) => 0x03,
+            Op::GET(_) => 0x04,
+            Op::SET(_) => 0x05,
+            Op::ADD => 0x10,
+            Op::SUB => 0x11,
+            Op::MUL => 0x12,
+            Op::DIV => 0x13,
+            Op::NEG => 0x14,
+            Op::POS => 0x15,
+            Op::STEP => 0x20,
+            Op::HALT => 0xff,
+        }
+    }
+
+    fn operands(&self) -> Vec<u32> {
+        match self {
+            Op::PUSH(i) | Op::GET(i) | Op::SET(i) => vec![*i],
+            Op::LOAD(depth, slot) | Op::STORE(depth, slot) => vec![*depth, *slot],
+            _ => Vec::new(),
+        }
+    }
+
+    fn decode(opcode: u8, reader: &mut Reader) -> io::Result<Op> {
+        match opcode {
+            0x01 => Ok(Op::PUSH(reader.u32()?)),
+            0x02 => Ok(Op::LOAD(reader.u32()?, reader.u32()?)),
+            0x03 => Ok(Op::STORE(reader.u32()?, reader.u32()?)),
+            0x04 => Ok(Op::GET(reader.u32()?)),
+            0x05 => Ok(Op::SET(reader.u32()?)),
+            0x10 => Ok(Op::ADD),
+            0x11 => Ok(Op::SUB),
+            0x12 => Ok(Op::MUL),
+            0x13 => Ok(Op::DIV),
+            0x14 => Ok(Op::NEG),
+            0x15 => Ok(Op::POS),
+            0x20 => Ok(Op::STEP),
+            0xff => Ok(Op::HALT),
+            _ => Err(invalid(format!("unknown opcode {:#04x}", opcode))),
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Op::PUSH(i) => write!(f, "PUSH {}", i),
+            Op::LOAD(depth, slot) => write!(f, "LOAD {} {}", depth, slot),
+            Op::STORE(depth, slot) => write!(f, "STORE {} {}", depth, slot),
+            Op::GET(temp) => write!(f, "GET {}", temp),
+            Op::SET(temp) => write!(f, "SET {}", temp),
+            Op::ADD => write!(f, "ADD"),
+            Op::SUB => write!(f, "SUB"),
+            Op::MUL => write!(f, "MUL"),
+            Op::DIV => write!(f, "DIV"),
+            Op::NEG => write!(f, "NEG"),
+            Op::POS => write!(f, "POS"),
+            Op::STEP => write!(f, "STEP"),
+            Op::HALT => write!(f, "HALT"),
+        }
+    }
+}
+
+pub struct Procedure {
+    pub name: String,
+    pub entry: usize,
+}
+
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub spans: Vec<Option<Span>>,
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub procedures: Vec<Procedure>,
+    // the overflow mode and integer width the constants were folded with, which the program must run with
+    pub arithmetic: Arithmetic,
+    pub temps: u32,
+}
+
+impl Chunk {
+    fn new() -> Chunk {
+        Chunk {
+            code: Vec::new(),
+            spans: Vec::new(),
+            constants: Vec::new(),
+            names: Vec::new(),
+            procedures: Vec::new(),
+            arithmetic: Arithmetic::new(),
+            temps: 0,
+        }
+    }
+
+    pub fn is_compiled(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut out = Writer { bytes: Vec::new() };
+        out.bytes.extend_from_slice(MAGIC);
+        out.u16(VERSION);
+        out.u8(match self.arithmetic.overflow {
+            OverflowMode::TRAP => 1,
+            OverflowMode::WRAP => 2,
+            OverflowMode::SATURATE => 3,
+        });
+        out.u8(match self.arithmetic.width {
+            IntegerWidth::I32 => 1,
+            IntegerWidth::I64 => 2,
+            IntegerWidth::BIG => 3,
+        });
+        out.u32(self.temps);
+
+        out.u32(self.constants.len() as u32);
+        for constant in &self.constants {
+            out.value(constant)?;
+        }
+
+        out.u32(self.names.len() as u32);
+        for name in &self.names {
+            out.string(name);
+        }
+
+        out.u32(self.procedures.len() as u32);
+        for procedure in &self.procedures {
+            out.string(&procedure.name);
+            out.u32(procedure.entry as u32);
+        }
+
+        out.u32(self.code.len() as u32);
+        for op in &self.code {
+            out.u8(op.opcode());
+            for operand in op.operands() {
+                out.u32(operand);
+            }
+        }
+
+        let mut lines = Vec::new();
+        for (ip, span) in self.spans.iter().enumerate() {
+            if ip == 0 || self.spans[ip - 1] != *span {
+                lines.push((ip, span.unwrap_or_default()));
+            }
+        }
+        out.u32(lines.len() as u32);
+        for (ip, span) in lines {
+            out.u32(ip as u32);
+            out.u32(span.line as u32);
+            out.u32(span.column as u32);
+        }
+
+        Ok(out.bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Chunk> {
+        if !Chunk::is_compiled(bytes) {
+            return Err(invalid(String::from("not a compiled lsbasi program")));
+        }
+        let mut reader = Reader { bytes, pos: MAGIC.len() };
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(invalid(format!("unsupported bytecode version {}", version)));
+        }
+
+        let mut chunk = Chunk::new();
+        chunk.arithmetic.overflow = match reader.u8()? {
+            1 => OverflowMode::TRAP,
+            2 => OverflowMode::WRAP,
+            3 => OverflowMode::SATURATE,
+            mode => return Err(invalid(format!("unknown overflow mode {}", mode))),
+        };
+        chunk.arithmetic.width = match reader.u8()? {
+            1 => IntegerWidth::I32,
+            2 => IntegerWidth::I64,
+            3 => IntegerWidth::BIG,
+            width => return Err(invalid(format!("unknown integer width {}", width))),
+        };
+        chunk.temps = reader.u32()?;
+
+        for _ in 0..reader.u32()? {
+            chunk.constants.push(reader.value()?);
+        }
+        for _ in 0..reader.u32()? {
+            chunk.names.push(reader.string()?);
+        }
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let entry = reader.u32()? as usize;
+            chunk.procedures.push(Procedure { name, entry });
+        }
+        for _ in 0..reader.u32()? {
+            let opcode = reader.u8()?;
+            chunk.code.push(Op::decode(opcode, &mut reader)?);
+        }
+
+        chunk.spans = vec![None; chunk.code.len()];
+        let mut lines = Vec::new();
+        for _ in 0..reader.u32()? {
+            let ip = reader.u32()? as usize;
+            let line = reader.u32()? as usize;
+            let column = reader.u32()? as usize;
+            let span = if line == 0 { None } else { Some(Span { line, column }) };
+            lines.push((ip, span));
+        }
+        for (i, (ip, span)) in lines.iter().enumerate() {
+            let end = lines.get(i + 1).map_or(chunk.code.len(), |next| next.0);
+            for slot in chunk.spans.iter_mut().take(end).skip(*ip) {
+                *slot = *span;
+            }
+        }
+
+        chunk.validate()?;
+        Ok(chunk)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        if self.procedures.is_empty() {
+            return Err(invalid(String::from("missing entry procedure")));
+        }
+        for procedure in &self.procedures {
+            if procedure.entry >= self.code.len() {
+                return Err(invalid(format!("procedure {} starts outside the code", procedure.name)));
+            }
+        }
+        for op in &self.code {
+            let in_range = match op {
+                Op::PUSH(i) => (*i as usize) < self.constants.len(),
+                Op::LOAD(depth, slot) | Op::STORE(depth, slot) => *depth == 0 && (*slot as usize) < self.names.len(),
+                Op::GET(temp) | Op::SET(temp) => *temp < self.temps,
+                _ => true,
+            };
+            if !in_range {
+                return Err(invalid(format!("operand of {} out of range", op)));
+            }
+        }
+        if self.code.last() != Some(&Op::HALT) {
+            return Err(invalid(String::from("code does not end with HALT")));
+        }
+        Ok(())
+    }
+
+    fn comment(&self, op: &Op) -> String {
+        match op {
+            Op::PUSH(i) => format!("; {}", self.constants[*i as usize]),
+            Op::LOAD(0, slot) | Op::STORE(0, slot) => format!("; {}", self.names[*slot as usize]),
+            _ => String::new(),
+        }
+    }
+}
+
+impl Display for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "; lsbasi bytecode v{}", VERSION)?;
+        writeln!(f, "; overflow: {:?}", self.arithmetic.overflow)?;
+        writeln!(f, "; integer: {:?}", self.arithmetic.width)?;
+        if self.temps > 0 {
+            writeln!(f, "; temporaries: {}", self.temps)?;
+        }
+
+        writeln!(f, "\n.constants")?;
+        for (i, constant) in self.constants.iter().enumerate() {
+            writeln!(f, "    {:>4}  {:<8} {}", i, constant.type_name(), constant)?;
+        }
+
+        writeln!(f, "\n.names")?;
+        for (i, name) in self.names.iter().enumerate() {
+            writeln!(f, "    {:>4}  {}", i, name)?;
+        }
+
+        writeln!(f, "\n.procedures")?;
+        for procedure in &self.procedures {
+            writeln!(f, "    {:04}  {}", procedure.entry, procedure.name)?;
+        }
+
+        writeln!(f, "\n.code")?;
+        for (ip, op) in self.code.iter().enumerate() {
+            if let Some(procedure) = self.procedures.iter().find(|p| p.entry == ip) {
+                writeln!(f, "{}:", procedure.name)?;
+            }
+            let line = match self.spans[ip] {
+                Some(span) => format!("{}:{}", span.line, span.column),
+                None => String::from("-"),
+            };
+            writeln!(f, "    {:04}  {:>7}  {:<10} {}", ip, line, op.to_string(), self.comment(op))?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn value(&mut self, value: &Value) -> io::Result<()> {
+        match value {
+            Value::UNIT => self.u8(0),
+            Value::INT(n) => {
+                self.u8(1);
+                self.bytes.extend_from_slice(&n.to_le_bytes());
+            },
+            Value::BIGINT(n) => {
+                self.u8(2);
+                self.string(&n.to_string());
+            },
+            Value::REAL(r) => {
+                self.u8(3);
+                self.bytes.extend_from_slice(&r.to_le_bytes());
+            },
+            Value::BOOLEAN(b) => {
+                self.u8(4);
+                self.u8(*b as u8);
+            },
+            Value::CHAR(c) => {
+                self.u8(5);
+                self.u32(*c as u32);
+            },
+            Value::STRING(s) => {
+                self.u8(6);
+                self.string(s);
+            },
+            _ => return Err(invalid(format!("{} constants cannot be serialized", value.type_name()))),
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated bytecode file"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<[u8; 8]> {
+        Ok(self.take(8)?.try_into().unwrap())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+
+    fn value(&mut self) -> io::Result<Value> {
+        match self.u8()? {
+            0 => Ok(Value::UNIT),
+            1 => Ok(Value::INT(i64::from_le_bytes(self.u64()?))),
+            2 => {
+                let digits = self.string()?;
+                digits.parse::<BigInt>().map(Value::BIGINT).map_err(|e| invalid(e.to_string()))
+            },
+            3 => Ok(Value::REAL(f64::from_le_bytes(self.u64()?))),
+            4 => Ok(Value::BOOLEAN(self.u8()? != 0)),
+            5 => char::from_u32(self.u32()?).map(Value::CHAR).ok_or_else(|| invalid(String::from("invalid CHAR constant"))),
+            6 => Ok(Value::STRING(self.string()?)),
+            tag => Err(invalid(format!("unknown constant tag {}", tag))),
+        }
+    }
+}
+
+fn binary_op(genre: CalcTokenType) -> Op {
+    match genre {
+        CalcTokenType::PLUS => Op::ADD,
+        CalcTokenType::MINUS => Op::SUB,
+        CalcTokenType::MUL => Op::MUL,
+        _ => Op::DIV,
+    }
+}
+
+fn unary_op(genre: CalcTokenType) -> Op {
+    match genre {
+        CalcTokenType::MINUS => Op::NEG,
+        _ => Op::POS,
+    }
+}
+
+pub struct Compiler {
+    chunk: Chunk,
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler {
+            chunk: Chunk::new(),
+        }
+    }
+
+    fn emit(&mut self, op: Op, span: Option<Span>) {
+        self.chunk.code.push(op);
+        self.chunk.spans.push(span);
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.chunk.constants.push(value);
+        (self.chunk.constants.len() - 1) as u32
+    }
+
+    pub fn compile_function(mut self, function: &Function, arithmetic: Arithmetic) -> Chunk {
+        self.chunk.names = function.names.clone();
+        self.chunk.arithmetic = arithmetic;
+        self.chunk.temps = function.temps as u32;
+        let entry = self.chunk.code.len();
+        self.chunk.procedures.push(Procedure { name: String::from("main"), entry });
+        for block in &function.blocks {
+            for ((instr, span), sources) in block.code.iter().zip(&block.spans).zip(&block.sources) {
+                match instr {
+                    Instr::COPY(dest, source) => {
+                        self.load(source, sources[0]);
+                        self.store(dest, *span);
+                    },
+                    Instr::UNARY(dest, op, operand) => {
+                        self.load(operand, sources[0]);
+                        self.emit(unary_op(*op), *span);
+                        self.store(dest, *span);
+                    },
+                    Instr::BINARY(dest, op, left, right) => {
+                        self.load(left, sources[0]);
+                        self.load(right, sources[1]);
+                        self.emit(binary_op(*op), *span);
+                        self.store(dest, *span);
+                    },
+                    Instr::STEP => self.emit(Op::STEP, *span),
+                }
+            }
+        }
+        self.emit(Op::HALT, None);
+        self.chunk
+    }
+
+    fn load(&mut self, operand: &Operand, span: Option<Span>) {
+        let op = match operand {
+            Operand::CONST(value) => Op::PUSH(self.constant(value.clone())),
+            Operand::VAR(depth, slot) => Op::LOAD(*depth as u32, *slot as u32),
+            Operand::TEMP(temp) => Op::GET(*temp as u32),
+        };
+        self.emit(op, span);
+    }
+
+    fn store(&mut self, operand: &Operand, span: Option<Span>) {
+        match operand {
+            Operand::VAR(depth, slot) => self.emit(Op::STORE(*depth as u32, *slot as u32), span),
+            Operand::TEMP(temp) => self.emit(Op::SET(*temp as u32), span),
+            Operand::CONST(_) => {},
+        }
+    }
+
+    pub fn compile(mut self, program: &Program, arithmetic: Arithmetic) -> Chunk {
+        self.chunk.names = program.globals.clone();
+        self.chunk.arithmetic = arithmetic;
+        let entry = self.chunk.code.len();
+        self.chunk.procedures.push(Procedure { name: String::from("main"), entry });
+        TreeCompiler::new(&mut self, &program.slots).visit(&program.ast, program.root);
+        self.emit(Op::HALT, None);
+        self.chunk
+    }
+}
+
+// compiles straight from the tree, without going through the IR
+struct TreeCompiler<'a> {
+    compiler: &'a mut Compiler,
+    slots: &'a SideTable<(usize, usize)>,
+}
+
+impl<'a> TreeCompiler<'a> {
+    fn new(compiler: &'a mut Compiler, slots: &'a SideTable<(usize, usize)>) -> TreeCompiler<'a> {
+        TreeCompiler {
+            compiler,
+            slots,
+        }
+    }
+}
+
+impl<'a> Visitor for TreeCompiler<'a> {
+    type Result = ();
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) {
+        walk_binop(self, ast, node);
+        self.compiler.emit(binary_op(node.op.genre), Some(node.op.span));
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) {
+        let index = self.compiler.constant(node.token.value.clone().unwrap_or(Value::UNIT));
+        self.compiler.emit(Op::PUSH(index), Some(node.token.span));
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) {
+        walk_unaryop(self, ast, node);
+        self.compiler.emit(unary_op(node.op.genre), Some(node.op.span));
+    }
+
+    fn visit_compound(&mut self, ast: &ASTArena, _id: NodeId, node: &Compound) {
+        self.compiler.emit(Op::STEP, None);
+        walk_compound(self, ast, node);
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) {
+        self.compiler.emit(Op::STEP, Some(node.op.span));
+        if let AST::VAR(_) = &ast[node.left] {
+            self.visit(ast, node.right);
+            let (depth, slot) = self.slots[node.left];
+            self.compiler.emit(Op::STORE(depth as u32, slot as u32), Some(node.op.span));
+        }
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, id: NodeId, node: &Var) {
+        let (depth, slot) = self.slots[id];
+        self.compiler.emit(Op::LOAD(depth as u32, slot as u32), Some(node.token.span));
+    }
+
+    fn visit_noop(&mut self, _ast: &ASTArena, _id: NodeId, _node: &NoOp) {
+        self.compiler.emit(Op::STEP, None);
+    }
+}
+
+pub struct VM<'a> {
+    chunk: &'a Chunk,
+    runtime: &'a mut Runtime,
+    stack: Vec<Value>,
+    temps: Vec<Value>,
+    ip: usize,
+}
+
+impl<'a> VM<'a> {
+    pub fn new(chunk: &'a Chunk, runtime: &'a mut Runtime) -> VM<'a> {
+        VM {
+            chunk,
+            runtime,
+            stack: Vec::new(),
+            temps: vec![Value::UNIT; chunk.temps as usize],
+            ip: 0,
+        }
+    }
+
+    pub fn run(&mut self, inputs: &[(String, Value)]) -> Result<Value, InterpretError> {
+        self.runtime.arithmetic = self.chunk.arithmetic;
+        self.runtime.start(&self.chunk.names, None, inputs)?;
+        self.ip = self.chunk.procedures[0].entry;
+        loop {
+            let op = self.chunk.code[self.ip];
+            let span = self.chunk.spans[self.ip];
+            self.ip += 1;
+
+            if op == Op::HALT {
+                return Ok(Value::UNIT);
+            }
+            if let Err(code) = self.execute(op) {
+                let error = match span {
+                    Some(span) => RuntimeError::new(code, span),
+                    None => RuntimeError::unspanned(code),
+                };
+                return Err(self.runtime.fail(error));
+            }
+        }
+    }
+
+    // a chunk that passed validation can still pop more than it pushed
+    fn pop(&mut self) -> Result<Value, ErrorCode> {
+        self.stack.pop().ok_or(ErrorCode::STACKUNDERFLOW)
+    }
+
+    fn execute(&mut self, op: Op) -> Result<(), ErrorCode> {
+        match op {
+            Op::PUSH(index) => {
+                let value = self.chunk.constants[index as usize].clone();
+                self.stack.push(self.runtime.arithmetic.literal(value)?);
+            },
+            Op::LOAD(depth, slot) => {
+                let value = self.runtime.lookup(depth as usize, slot as usize)?;
+                self.stack.push(value);
+            },
+            Op::STORE(depth, slot) => {
+                let value = self.pop()?;
+                self.runtime.assign(depth as usize, slot as usize, value)?;
+            },
+            Op::GET(temp) => self.stack.push(self.temps[temp as usize].clone()),
+            Op::SET(temp) => self.temps[temp as usize] = self.pop()?,
+            Op::ADD => self.binary(CalcTokenType::PLUS)?,
+            Op::SUB => self.binary(CalcTokenType::MINUS)?,
+            Op::MUL => self.binary(CalcTokenType::MUL)?,
+            Op::DIV => self.binary(CalcTokenType::DIV)?,
+            Op::NEG => {
+                let value = self.pop()?.neg(self.runtime.arithmetic)?;
+                self.stack.push(value);
+            },
+            Op::POS => {
+                let value = self.pop()?.pos()?;
+                self.stack.push(value);
+            },
+            Op::STEP => self.runtime.step()?,
+            Op::HALT => {},
+        }
+        Ok(())
+    }
+
+    fn binary(&mut self, op: CalcTokenType) -> Result<(), ErrorCode> {
+        let right = self.pop()?;
+        let left = self.pop()?;
+        self.stack.push(left.arith(op, &right, self.runtime.arithmetic)?);
+        Ok(())
+    }
+}
+
+pub fn compile(program: &Program, arithmetic: Arithmetic) -> Chunk {
+    Compiler::new().compile(program, arithmetic)
+}
+
+pub fn compile_function(function: &Function, arithmetic: Arithmetic) -> Chunk {
+    Compiler::new().compile_function(function, arithmetic)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{Chunk, Op, VM, compile};
+    use crate::{Arithmetic, ErrorCode, InterpretError, Options, Program, Span};
+
+    fn chunk() -> Chunk {
+        let program = Program::parse(&String::from("BEGIN a := 2; b := a + 1 END.")).unwrap_or_else(|e| panic!("{}", e));
+        compile(&program, Arithmetic::new())
+    }
+
+    // the error loading `bytes` fails with
+    fn rejected(bytes: &[u8]) -> io::Error {
+        match Chunk::from_bytes(bytes) {
+            Ok(_) => panic!("{:?} loaded", bytes),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn saved_chunk_loads() {
+        let chunk = chunk();
+        let loaded = Chunk::from_bytes(&chunk.to_bytes().unwrap()).unwrap();
+        assert_eq!(loaded.to_string(), chunk.to_string());
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut bytes = chunk().to_bytes().unwrap();
+        bytes[0] = b'X';
+        let error = rejected(&bytes);
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "not a compiled lsbasi program");
+    }
+
+    #[test]
+    fn wrong_version() {
+        let mut bytes = chunk().to_bytes().unwrap();
+        bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+        let error = rejected(&bytes);
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "unsupported bytecode version 2");
+    }
+
+    #[test]
+    fn truncated_chunk() {
+        let bytes = chunk().to_bytes().unwrap();
+        for len in 4..bytes.len() {
+            let error = rejected(&bytes[..len]);
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof, "cut at {}", len);
+        }
+    }
+
+    #[test]
+    fn out_of_range_operands() {
+        let cases = [
+            (Op::PUSH(0), Op::PUSH(9), "operand of PUSH 9 out of range"),
+            (Op::STORE(0, 0), Op::STORE(0, 7), "operand of STORE 0 7 out of range"),
+            (Op::LOAD(0, 0), Op::LOAD(1, 0), "operand of LOAD 1 0 out of range"),
+        ];
+        for (valid, invalid, message) in cases {
+            let mut chunk = chunk();
+            let ip = chunk.code.iter().position(|op| *op == valid).unwrap();
+            chunk.code[ip] = invalid;
+            let error = rejected(&chunk.to_bytes().unwrap());
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), message);
+        }
+    }
+    #[test]
+    fn stack_underflow() {
+        let mut chunk = chunk();
+        let ip = chunk.code.iter().position(|op| *op == Op::PUSH(0)).unwrap();
+        chunk.code.remove(ip);
+        chunk.spans.remove(ip);
+        let chunk = Chunk::from_bytes(&chunk.to_bytes().unwrap()).unwrap();
+        let mut runtime = Options::new().runtime();
+        match VM::new(&chunk, &mut runtime).run(&[]) {
+            Err(InterpretError::RUNTIME(error)) => {
+                assert!(matches!(error.code, ErrorCode::STACKUNDERFLOW), "{}", error);
+                assert_eq!(error.span, Some(Span { line: 1, column: 9 }));
+            },
+            _ => panic!("popped an empty stack"),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use super::{
+    AST, ASTArena, Arithmetic, Assign, BinOp, CalcTokenType, ErrorCode, IntegerWidth, NodeId, Num, OverflowMode, Program, Span,
+    UnaryOp, Value, Var,
+    visit::{VisitResult, Visitor},
+};
+
+const PRELUDE: &str = r#"#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef struct {
+    int defined;
+    int64_t value;
+} lsb_integer;
+
+static void lsb_dump(int stack);
+
+static void lsb_fail(const char *message) {
+    printf("%s", message);
+    lsb_dump(1);
+    printf("\n");
+    exit(1);
+}
+
+static void lsb_member(const char *name, lsb_integer member, int stack) {
+    if (!member.defined) {
+        return;
+    }
+    if (stack) {
+        printf("\n   %-20s: %" PRId64, name, member.value);
+    } else {
+        printf("%s: %" PRId64 "\n", name, member.value);
+    }
+}
+
+static lsb_integer lsb_fit(__int128 exact, const char *overflow) {
+    lsb_integer result = { 1, 0 };
+    if (exact >= LSB_MIN && exact <= LSB_MAX) {
+        result.value = (int64_t) exact;
+        return result;
+    }
+    switch (LSB_OVERFLOW) {
+    case LSB_WRAP:
+        result.value = LSB_BITS == 32 ? (int64_t) (int32_t) exact : (int64_t) exact;
+        return result;
+    case LSB_SATURATE:
+        result.value = exact < LSB_MIN ? LSB_MIN : LSB_MAX;
+        return result;
+    default:
+        lsb_fail(overflow);
+        return result;
+    }
+}
+
+static inline lsb_integer lsb_literal(int64_t value) {
+    lsb_integer result = { 1, value };
+    return result;
+}
+
+static inline lsb_integer lsb_out_of_range(const char *message) {
+    lsb_integer result = { 0, 0 };
+    lsb_fail(message);
+    return result;
+}
+
+static inline lsb_integer lsb_load(lsb_integer member, const char *undefined) {
+    if (!member.defined) {
+        lsb_fail(undefined);
+    }
+    return member;
+}
+
+static inline lsb_integer lsb_binary(char op, lsb_integer left, lsb_integer right, const char *overflow) {
+    __int128 a = left.value, b = right.value;
+    switch (op) {
+    case '+':
+        return lsb_fit(a + b, overflow);
+    case '-':
+        return lsb_fit(a - b, overflow);
+    default:
+        return lsb_fit(a * b, overflow);
+    }
+}
+
+static inline lsb_integer lsb_divide(lsb_integer left, lsb_integer right, const char *overflow, const char *by_zero) {
+    if (right.value == 0) {
+        lsb_fail(by_zero);
+    }
+    return lsb_fit((__int128) left.value / right.value, overflow);
+}
+
+static inline lsb_integer lsb_negate(lsb_integer operand, const char *overflow) {
+    return lsb_fit(-(__int128) operand.value, overflow);
+}
+"#;
+
+struct Emitter<'a> {
+    program: &'a Program,
+    arithmetic: Arithmetic,
+    out: String,
+    temps: usize,
+}
+
+impl<'a> Emitter<'a> {
+    fn field(&self, id: NodeId) -> String {
+        field(&self.program.globals[self.program.slots[id].1])
+    }
+
+    fn temp(&mut self, expression: String) -> String {
+        let name = format!("t{}", self.temps);
+        self.temps += 1;
+        self.out += &format!("        lsb_integer {} = {};\n", name, expression);
+        name
+    }
+}
+
+// the C expression holding the value of a node
+impl VisitResult for Result<String, String> {
+    fn output() -> Self {
+        Ok(String::new())
+    }
+
+    fn branch(&self) -> bool {
+        self.is_err()
+    }
+}
+
+impl<'a> Visitor for Emitter<'a> {
+    type Result = Result<String, String>;
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) -> Result<String, String> {
+        let left = self.visit(ast, node.left)?;
+        let right = self.visit(ast, node.right)?;
+        let span = node.op.span;
+        let overflow = failure(ErrorCode::OVERFLOW(node.op.genre), span);
+        let op = match node.op.genre {
+            CalcTokenType::PLUS => '+',
+            CalcTokenType::MINUS => '-',
+            CalcTokenType::MUL => '*',
+            _ => {
+                let by_zero = failure(ErrorCode::DIVISIONBYZERO, span);
+                return Ok(self.temp(format!("lsb_divide({}, {}, {}, {})", left, right, overflow, by_zero)));
+            },
+        };
+        Ok(self.temp(format!("lsb_binary('{}', {}, {}, {})", op, left, right, overflow)))
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) -> Result<String, String> {
+        let literal = match self.arithmetic.literal(node.token.value.clone().unwrap_or(Value::UNIT)) {
+            Ok(Value::INT(n)) => format!("lsb_literal(INT64_C({}))", n),
+            Ok(value) => return Err(format!("{} literals are not supported by the C backend", value.type_name())),
+            Err(code) => format!("lsb_out_of_range({})", failure(code, node.token.span)),
+        };
+        Ok(self.temp(literal))
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) -> Result<String, String> {
+        let operand = self.visit(ast, node.expr)?;
+        match node.op.genre {
+            CalcTokenType::MINUS => {
+                let overflow = failure(ErrorCode::OVERFLOW(CalcTokenType::MINUS), node.op.span);
+                Ok(self.temp(format!("lsb_negate({}, {})", operand, overflow)))
+            },
+            _ => Ok(operand),
+        }
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) -> Result<String, String> {
+        if let AST::VAR(_) = &ast[node.left] {
+            self.out += &format!("    {{ /* line {} */\n", node.op.span.line);
+            let value = self.visit(ast, node.right)?;
+            self.out += &format!("        main_frame.{} = {};\n    }}\n", self.field(node.left), value);
+        }
+        Ok(String::new())
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, id: NodeId, node: &Var) -> Result<String, String> {
+        let undefined = failure(ErrorCode::UNDEFINEDVARIABLE(node.name()), node.token.span);
+        let load = format!("lsb_load(main_frame.{}, {})", self.field(id), undefined);
+        Ok(self.temp(load))
+    }
+}
+
+// what the interpreter prints for the error, as a C string literal
+fn failure(code: ErrorCode, span: Span) -> String {
+    format!("\"{}\"", escape(&crate::failure(code, span)))
+}
+
+// octal escapes, which read the same in C and in GNU as
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            },
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped += &format!("\\{:03o}", byte),
+        }
+    }
+    escaped
+}
+
+fn field(name: &str) -> String {
+    let mut field = String::from("v_");
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            field.push(c);
+        } else {
+            field += &format!("_u{:x}", c as u32);
+        }
+    }
+    field
+}
+
+pub fn emit(program: &Program, mut arithmetic: Arithmetic) -> Result<String, String> {
+    if let Some(mode) = program.overflow {
+        arithmetic.overflow = mode;
+    }
+    let bits = match arithmetic.width {
+        IntegerWidth::I32 => 32,
+        IntegerWidth::I64 => 64,
+        IntegerWidth::BIG => return Err(String::from("arbitrary-precision integers are not supported by the C backend")),
+    };
+    let overflow = match arithmetic.overflow {
+        OverflowMode::TRAP => "LSB_TRAP",
+        OverflowMode::WRAP => "LSB_WRAP",
+        OverflowMode::SATURATE => "LSB_SATURATE",
+    };
+
+    let mut emitter = Emitter {
+        program,
+        arithmetic,
+        out: String::new(),
+        temps: 0,
+    };
+    emitter.visit(&program.ast, program.root)?;
+
+    let mut c = String::from("/* generated by lsbasi emit-c */\n");
+    c += "#define LSB_TRAP 0\n#define LSB_WRAP 1\n#define LSB_SATURATE 2\n";
+    c += &format!("#define LSB_OVERFLOW {}\n#define LSB_BITS {}\n", overflow, bits);
+    if bits == 32 {
+        c += "#define LSB_MIN INT32_MIN\n#define LSB_MAX INT32_MAX\n\n";
+    } else {
+        c += "#define LSB_MIN INT64_MIN\n#define LSB_MAX INT64_MAX\n\n";
+    }
+    c += PRELUDE;
+
+    c += "\nstatic struct {\n";
+    for name in &program.globals {
+        c += &format!("    lsb_integer {};\n", field(name));
+    }
+    if program.globals.is_empty() {
+        c += "    int empty;\n";
+    }
+    c += "} main_frame;\n\nstatic void lsb_dump(int stack) {\n";
+    for name in &program.globals {
+        c += &format!("    lsb_member({:?}, main_frame.{}, stack);\n", name, field(name));
+    }
+    c += "    (void) stack;\n}\n\nint main(void) {\n";
+    c += &emitter.out;
+    c += "    lsb_dump(0);\n    return 0;\n}\n";
+    Ok(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::emit;
+    use crate::prepare;
+    use crate::tests::{ARITHMETICS, PROGRAMS, cc_output, options, output};
+
+    #[test]
+    fn compiled_program_matches_interpreter() {
+        for &arithmetic in ARITHMETICS {
+            for text in PROGRAMS {
+                let options = options(arithmetic);
+                let program = prepare(&text.to_string(), &options).unwrap_or_else(|e| panic!("{}", e));
+                let source = emit(&program, arithmetic).unwrap();
+                let stdout = cc_output(&source, "c");
+                assert_eq!(stdout, output(text, &options), "{} with {:?}", text, arithmetic);
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use super::{
+    AST, ASTArena, Arithmetic, Assign, BinOp, CalcTokenType, ErrorCode, IntegerWidth, NodeId, Num, OverflowMode, Program,
+    Span, UnaryOp, Value, Var, failure,
+    visit::Visitor,
+};
+
+struct Emitter<'a> {
+    program: &'a Program,
+    arithmetic: Arithmetic,
+    body: String,
+    data: Vec<u8>,
+    strings: HashMap<String, (usize, usize)>,
+}
+
+impl<'a> Emitter<'a> {
+    fn emit(&mut self, line: &str) {
+        self.body += "    ";
+        self.body += line;
+        self.body += "\n";
+    }
+
+    fn string(&mut self, value: &str) -> String {
+        let data = &mut self.data;
+        let (offset, len) = *self.strings.entry(value.to_string()).or_insert_with(|| {
+            data.extend_from_slice(value.as_bytes());
+            (data.len() - value.len(), value.len())
+        });
+        format!("(i32.const {}) (i32.const {})", offset, len)
+    }
+
+    fn write(&mut self, value: &str) -> String {
+        format!("(call $write_str {})", self.string(value))
+    }
+
+    // the interpreter's report of the error, as the offset and length $fail takes
+    fn failure(&mut self, code: ErrorCode, span: Span) -> String {
+        self.string(&failure(code, span))
+    }
+
+    fn fail(&mut self, code: ErrorCode, span: Span) -> String {
+        format!("(call $fail {})", self.failure(code, span))
+    }
+
+    // `overflowed` tests whether the 64-bit result $r wrapped, `saturated` is the bound it clamps to
+    fn helper(&mut self, name: &str, op: CalcTokenType, compute: &str, overflowed: &str, saturated: &str) -> String {
+        let mut helper = format!("  (func ${} (param $a i64) (param $b i64) (param $overflow i32) (param $overflow_length i32)", name);
+        if op == CalcTokenType::DIV {
+            helper += " (param $by_zero i32) (param $by_zero_length i32)";
+        }
+        helper += " (result i64)\n";
+        helper += "    (local $r i64)\n";
+        if op == CalcTokenType::DIV {
+            helper += "    (if (i64.eqz (local.get $b)) (then (call $fail (local.get $by_zero) (local.get $by_zero_length))))\n";
+        }
+        helper += &format!("    (local.set $r {})\n", compute);
+
+        let trap = String::from("(call $fail (local.get $overflow) (local.get $overflow_length))");
+        match self.arithmetic.width {
+            IntegerWidth::I32 => {
+                let action = match self.arithmetic.overflow {
+                    OverflowMode::TRAP => trap,
+                    OverflowMode::WRAP => String::from("(return (i64.extend_i32_s (i32.wrap_i64 (local.get $r))))"),
+                    OverflowMode::SATURATE => String::from(
+                        "(return (select (i64.const -2147483648) (i64.const 2147483647) (i64.lt_s (local.get $r) (i64.const 0))))",
+                    ),
+                };
+                helper += &format!(
+                    "    (if (i64.ne (local.get $r) (i64.extend_i32_s (i32.wrap_i64 (local.get $r)))) (then {}))\n",
+                    action,
+                );
+            },
+            _ => {
+                let action = match self.arithmetic.overflow {
+                    OverflowMode::TRAP => Some(trap),
+                    OverflowMode::WRAP => None,
+                    OverflowMode::SATURATE => Some(format!("(return {})", saturated)),
+                };
+                if let Some(action) = action {
+                    helper += &format!("    (if {} (then {}))\n", overflowed, action);
+                }
+            },
+        }
+        helper += "    (local.get $r))\n";
+        helper
+    }
+
+    fn helpers(&mut self) -> String {
+        let min = "(i64.const -9223372036854775808)";
+        let max = "(i64.const 9223372036854775807)";
+        let negative = |operand: &str| format!("(i64.lt_s {} (i64.const 0))", operand);
+        let mut helpers = String::new();
+
+        helpers += &self.helper(
+            "add",
+            CalcTokenType::PLUS,
+            "(i64.add (local.get $a) (local.get $b))",
+            &negative("(i64.and (i64.xor (local.get $a) (local.get $r)) (i64.xor (local.get $b) (local.get $r)))"),
+            &format!("(select {} {} {})", min, max, negative("(local.get $b)")),
+        );
+        helpers += &self.helper(
+            "sub",
+            CalcTokenType::MINUS,
+            "(i64.sub (local.get $a) (local.get $b))",
+            &negative("(i64.and (i64.xor (local.get $a) (local.get $b)) (i64.xor (local.get $a) (local.get $r)))"),
+            &format!("(select {} {} {})", max, min, negative("(local.get $b)")),
+        );
+        // r / a == b detects a wrapped product; the divisor is replaced where it would be 0 or trap on MIN / -1
+        let divisor = "(select (i64.const 1) (local.get $a) (i32.or (i64.eqz (local.get $a)) (i64.eq (local.get $a) (i64.const -1))))";
+        helpers += &self.helper(
+            "mul",
+            CalcTokenType::MUL,
+            "(i64.mul (local.get $a) (local.get $b))",
+            &format!(
+                "(i32.or (i32.and (i64.eq (local.get $a) (i64.const -1)) (i64.eq (local.get $b) {})) \
+                 (i32.and (i32.and (i64.ne (local.get $a) (i64.const 0)) (i64.ne (local.get $a) (i64.const -1))) \
+                 (i64.ne (i64.div_s (local.get $r) {}) (local.get $b))))",
+                min, divisor,
+            ),
+            &format!("(select {} {} {})", min, max, negative("(i64.xor (local.get $a) (local.get $b))")),
+        );
+        // i64.div_s traps on MIN / -1, so a / -1 is computed as the (wrapping) negation 0 - a instead
+        let quotient = match self.arithmetic.width {
+            IntegerWidth::I32 => "(i64.div_s (local.get $a) (local.get $b))",
+            _ => "(if (result i64) (i64.eq (local.get $b) (i64.const -1)) \
+                  (then (i64.sub (i64.const 0) (local.get $a))) (else (i64.div_s (local.get $a) (local.get $b))))",
+        };
+        helpers += &self.helper(
+            "div",
+            CalcTokenType::DIV,
+            quotient,
+            &format!("(i32.and (i64.eq (local.get $a) {}) (i64.eq (local.get $b) (i64.const -1)))", min),
+            max,
+        );
+        helpers
+    }
+
+    fn dump(&mut self) -> String {
+        let mut dump = String::from("  (func $dump (param $stack i32)\n");
+        for (slot, name) in self.program.globals.iter().enumerate() {
+            let stacked = self.write(&format!("\n   {:<20}: ", name));
+            let plain = self.write(&format!("{}: ", name));
+            let newline = self.write("\n");
+            dump += &format!("    (if (global.get $d{}) (then\n", slot);
+            dump += &format!("      (if (local.get $stack) (then {}) (else {}))\n", stacked, plain);
+            dump += &format!("      (call $write_int (global.get $v{}))\n", slot);
+            dump += &format!("      (if (i32.eqz (local.get $stack)) (then {}))))\n", newline);
+        }
+        dump += "  )\n";
+        dump
+    }
+}
+
+impl<'a> Visitor for Emitter<'a> {
+    type Result = Result<(), String>;
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) -> Result<(), String> {
+        self.visit(ast, node.left)?;
+        self.visit(ast, node.right)?;
+        let mut messages = self.failure(ErrorCode::OVERFLOW(node.op.genre), node.op.span);
+        let helper = match node.op.genre {
+            CalcTokenType::PLUS => "$add",
+            CalcTokenType::MINUS => "$sub",
+            CalcTokenType::MUL => "$mul",
+            _ => {
+                messages += " ";
+                messages += &self.failure(ErrorCode::DIVISIONBYZERO, node.op.span);
+                "$div"
+            },
+        };
+        self.emit(&format!("(call {} {})", helper, messages));
+        Ok(())
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) -> Result<(), String> {
+        match self.arithmetic.literal(node.token.value.clone().unwrap_or(Value::UNIT)) {
+            Ok(Value::INT(n)) => self.emit(&format!("i64.const {}", n)),
+            Ok(value) => return Err(format!("{} literals are not supported by the WebAssembly backend", value.type_name())),
+            Err(code) => {
+                let fail = self.fail(code, node.token.span);
+                self.emit(&fail);
+                self.emit("unreachable");
+            },
+        }
+        Ok(())
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) -> Result<(), String> {
+        if node.op.genre != CalcTokenType::MINUS {
+            return self.visit(ast, node.expr);
+        }
+        // -x is 0 - x, which overflows (and saturates) exactly when negation does
+        self.emit("i64.const 0");
+        self.visit(ast, node.expr)?;
+        let overflow = self.failure(ErrorCode::OVERFLOW(CalcTokenType::MINUS), node.op.span);
+        self.emit(&format!("(call $sub {})", overflow));
+        Ok(())
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) -> Result<(), String> {
+        if let AST::VAR(_) = &ast[node.left] {
+            let slot = self.program.slots[node.left].1;
+            self.emit(&format!(";; line {}", node.op.span.line));
+            self.visit(ast, node.right)?;
+            self.emit(&format!("global.set $v{}", slot));
+            self.emit(&format!("(global.set $d{} (i32.const 1))", slot));
+        }
+        Ok(())
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, id: NodeId, node: &Var) -> Result<(), String> {
+        let slot = self.program.slots[id].1;
+        let fail = self.fail(ErrorCode::UNDEFINEDVARIABLE(node.name()), node.token.span);
+        self.emit(&format!("(if (i32.eqz (global.get $d{})) (then {}))", slot, fail));
+        self.emit(&format!("global.get $v{}", slot));
+        Ok(())
+    }
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for byte in bytes {
+        match byte {
+            0x20..=0x7e if *byte != b'"' && *byte != b'\\' => escaped.push(*byte as char),
+            _ => escaped += &format!("\\{:02x}", byte),
+        }
+    }
+    escaped
+}
+
+pub fn emit(program: &Program, mut arithmetic: Arithmetic) -> Result<String, String> {
+    if let Some(mode) = program.overflow {
+        arithmetic.overflow = mode;
+    }
+    if arithmetic.width == IntegerWidth::BIG {
+        return Err(String::from("arbitrary-precision integers are not supported by the WebAssembly backend"));
+    }
+
+    let mut emitter = Emitter {
+        program,
+        arithmetic,
+        body: String::new(),
+        data: Vec::new(),
+        strings: HashMap::new(),
+    };
+    emitter.visit(&program.ast, program.root)?;
+    let helpers = emitter.helpers();
+    let dump = emitter.dump();
+    let newline = emitter.write("\n");
+
+    let mut wat = String::from(";; generated by lsbasi emit-wat\n(module\n");
+    wat += "  (import \"env\" \"write_str\" (func $write_str (param i32 i32)))\n";
+    wat += "  (import \"env\" \"write_int\" (func $write_int (param i64)))\n";
+    wat += "  (memory (export \"memory\") 1)\n";
+    for (slot, name) in program.globals.iter().enumerate() {
+        wat += &format!("  (global $v{} (mut i64) (i64.const 0)) ;; {}\n", slot, name);
+        wat += &format!("  (global $d{} (mut i32) (i32.const 0))\n", slot);
+    }
+
+    wat += "  (func $fail (param $message i32) (param $length i32)\n";
+    wat += "    (call $write_str (local.get $message) (local.get $length))\n";
+    wat += "    (call $dump (i32.const 1))\n";
+    wat += &format!("    {}\n", newline);
+    wat += "    unreachable)\n";
+    wat += &dump;
+    wat += &helpers;
+
+    wat += "  (func (export \"main\")\n";
+    wat += &emitter.body;
+    wat += "    (call $dump (i32.const 0)))\n";
+    wat += &format!("  (data (i32.const 0) \"{}\"))\n", escape(&emitter.data));
+    Ok(wat)
+}
+
+// built with --features wasm-tests, which also needs node on the PATH
+#[cfg(all(test, feature = "wasm-tests"))]
+mod tests {
+    use super::emit;
+    use crate::{Arithmetic, prepare};
+    use crate::tests::{ARITHMETICS, PROGRAMS, options, output, scratch};
+
+    // instantiates the module with the two imports emit-wat expects and prints what it writes
+    const RUNNER: &str = r#"
+const bytes = require("fs").readFileSync(process.argv[2]);
+let memory;
+let out = "";
+const env = {
+    write_str: (offset, length) => { out += Buffer.from(memory.buffer, offset, length).toString(); },
+    write_int: (value) => { out += value.toString(); },
+};
+WebAssembly.instantiate(bytes, { env: env }).then(({ instance }) => {
+    memory = instance.exports.memory;
+    try {
+        instance.exports.main();
+    } catch (e) {
+        if (!(e instanceof WebAssembly.RuntimeError)) throw e;
+    }
+    process.stdout.write(out);
+});
+"#;
+
+    fn module(text: &str, arithmetic: Arithmetic) -> Vec<u8> {
+        let program = prepare(&text.to_string(), &options(arithmetic)).unwrap_or_else(|e| panic!("{}", e));
+        let source = emit(&program, arithmetic).unwrap();
+        let binary = wat::parse_str(&source).unwrap_or_else(|e| panic!("{}\n{}", e, source));
+        wasmparser::Validator::new().validate_all(&binary).unwrap_or_else(|e| panic!("{}\n{}", e, source));
+        binary
+    }
+
+    fn node_output(binary: &[u8]) -> String {
+        let dir = scratch();
+        std::fs::write(dir.join("program.wasm"), binary).unwrap();
+        std::fs::write(dir.join("runner.js"), RUNNER).unwrap();
+        let run = std::process::Command::new("node").arg(dir.join("runner.js")).arg(dir.join("program.wasm")).output();
+        std::fs::remove_dir_all(&dir).ok();
+        let run = run.unwrap_or_else(|e| panic!("cannot run node, which the wasm-tests feature needs: {}", e));
+        assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+        String::from_utf8_lossy(&run.stdout).into_owned()
+    }
+
+    #[test]
+    fn module_validates() {
+        for &arithmetic in ARITHMETICS {
+            for text in PROGRAMS {
+                module(text, arithmetic);
+            }
+        }
+    }
+
+    #[test]
+    fn module_matches_interpreter() {
+        for &arithmetic in ARITHMETICS {
+            for text in PROGRAMS {
+                let stdout = node_output(&module(text, arithmetic));
+                assert_eq!(stdout, output(text, &options(arithmetic)), "{} with {:?}", text, arithmetic);
+            }
+        }
+    }
+}
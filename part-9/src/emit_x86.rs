@@ -0,0 +1,291 @@
+use super::{
+    AST, ASTArena, Arithmetic, Assign, BinOp, CalcTokenType, ErrorCode, IntegerWidth, NodeId, Num, OverflowMode, Program,
+    Span, UnaryOp, Value, Var, failure,
+    emit_c::escape,
+    visit::Visitor,
+};
+
+const RUNTIME: &str = r#"
+    .text
+lsb_fail:
+    andq $-16, %rsp
+    movq %rdi, %rsi
+    leaq .Lfail_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    movl $1, %edi
+    call lsb_dump
+    leaq .Lnewline(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    movl $1, %edi
+    call exit@PLT
+
+    .section .rodata
+.Lfail_format:
+    .string "%s"
+.Lnewline:
+    .string "\n"
+.Lmember:
+    .string "%s: %ld\n"
+.Lstack_member:
+    .string "\n   %-20s: %ld"
+"#;
+
+struct Emitter<'a> {
+    program: &'a Program,
+    arithmetic: Arithmetic,
+    text: String,
+    rodata: String,
+    labels: usize,
+}
+
+impl<'a> Emitter<'a> {
+    fn emit(&mut self, line: &str) {
+        self.text += "    ";
+        self.text += line;
+        self.text += "\n";
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        self.text += &format!("{}:\n", label);
+    }
+
+    fn string(&mut self, value: &str) -> String {
+        let label = self.label();
+        self.rodata += &format!("{}:\n    .string \"{}\"\n", label, escape(value));
+        label
+    }
+
+    // prints what the interpreter reports for the error and exits
+    fn fail(&mut self, code: ErrorCode, span: Span) {
+        let message = self.string(&failure(code, span));
+        self.emit(&format!("leaq {}(%rip), %rdi", message));
+        self.emit("call lsb_fail");
+    }
+
+    // reached with an overflowed 64-bit result in %rax
+    fn overflow(&mut self, op: CalcTokenType, span: Span, done: &str, saturate: &str) {
+        match self.arithmetic.overflow {
+            OverflowMode::TRAP => self.fail(ErrorCode::OVERFLOW(op), span),
+            OverflowMode::WRAP => {},
+            OverflowMode::SATURATE => self.emit(saturate),
+        }
+        self.emit(&format!("jmp {}", done));
+    }
+
+    // 32-bit results are computed exactly in 64 bits and range checked afterwards
+    fn fit(&mut self, op: CalcTokenType, span: Span, done: &str) {
+        if self.arithmetic.width != IntegerWidth::I32 {
+            return;
+        }
+        self.emit("movslq %eax, %rdx");
+        self.emit("cmpq %rax, %rdx");
+        self.emit(&format!("je {}", done));
+        match self.arithmetic.overflow {
+            OverflowMode::TRAP => self.fail(ErrorCode::OVERFLOW(op), span),
+            OverflowMode::WRAP => self.emit("movslq %eax, %rax"),
+            OverflowMode::SATURATE => {
+                self.emit("testq %rax, %rax");
+                self.emit("movq $2147483647, %rax");
+                self.emit(&format!("jns {}", done));
+                self.emit("movq $-2147483648, %rax");
+            },
+        }
+    }
+
+    fn dump(&mut self) {
+        self.text += "\nlsb_dump:\n";
+        self.emit("pushq %rbp");
+        self.emit("movq %rsp, %rbp");
+        self.emit("pushq %rbx");
+        self.emit("subq $8, %rsp");
+        self.emit("movl %edi, %ebx");
+        for slot in 0..self.program.globals.len() {
+            let name = self.string(&self.program.globals[slot].clone());
+            let skip = self.label();
+            let format = self.label();
+            self.emit(&format!("cmpq $0, main_frame+{}(%rip)", 16 * slot));
+            self.emit(&format!("je {}", skip));
+            self.emit(&format!("leaq {}(%rip), %rsi", name));
+            self.emit(&format!("movq main_frame+{}(%rip), %rdx", 16 * slot + 8));
+            self.emit("leaq .Lmember(%rip), %rdi");
+            self.emit("testl %ebx, %ebx");
+            self.emit(&format!("je {}", format));
+            self.emit("leaq .Lstack_member(%rip), %rdi");
+            self.place(&format);
+            self.emit("xorl %eax, %eax");
+            self.emit("call printf@PLT");
+            self.place(&skip);
+        }
+        self.emit("movq -8(%rbp), %rbx");
+        self.emit("leave");
+        self.emit("ret");
+    }
+}
+
+impl<'a> Visitor for Emitter<'a> {
+    type Result = Result<(), String>;
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) -> Result<(), String> {
+        self.visit(ast, node.left)?;
+        self.emit("pushq %rax");
+        self.visit(ast, node.right)?;
+        self.emit("movq %rax, %rcx");
+        self.emit("popq %rax");
+
+        let op = node.op.genre;
+        let span = node.op.span;
+        let done = self.label();
+        if op == CalcTokenType::DIV {
+            let nonzero = self.label();
+            self.emit("testq %rcx, %rcx");
+            self.emit(&format!("jne {}", nonzero));
+            self.fail(ErrorCode::DIVISIONBYZERO, span);
+            self.place(&nonzero);
+            if self.arithmetic.width == IntegerWidth::I64 {
+                // idiv faults on MIN / -1, so that quotient goes through the overflow policy instead
+                let divide = self.label();
+                self.emit("cmpq $-1, %rcx");
+                self.emit(&format!("jne {}", divide));
+                self.emit("movabsq $-9223372036854775808, %rdx");
+                self.emit("cmpq %rdx, %rax");
+                self.emit(&format!("jne {}", divide));
+                self.overflow(op, span, &done, "movabsq $9223372036854775807, %rax");
+                self.place(&divide);
+            }
+            self.emit("cqto");
+            self.emit("idivq %rcx");
+        } else {
+            let (instruction, sign, positive) = match op {
+                CalcTokenType::PLUS => ("addq %rcx, %rax", "%rcx", "jns"),
+                CalcTokenType::MINUS => ("subq %rcx, %rax", "%rcx", "js"),
+                _ => ("imulq %rcx, %rax", "%rdx", "jns"),
+            };
+            if op == CalcTokenType::MUL {
+                self.emit("movq %rax, %rdx");
+                self.emit("xorq %rcx, %rdx");
+            }
+            self.emit(instruction);
+            if self.arithmetic.width == IntegerWidth::I64 {
+                self.emit(&format!("jno {}", done));
+                // a + b overflows towards the sign of b, a - b away from it and a * b towards the sign of a ^ b
+                let saturate = format!(
+                    "testq {}, {}\n    movabsq $9223372036854775807, %rax\n    {} {}\n    movabsq $-9223372036854775808, %rax",
+                    sign, sign, positive, done,
+                );
+                self.overflow(op, span, &done, &saturate);
+            }
+        }
+        self.fit(op, span, &done);
+        self.place(&done);
+        Ok(())
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) -> Result<(), String> {
+        self.visit(ast, node.expr)?;
+        if node.op.genre == CalcTokenType::MINUS {
+            let done = self.label();
+            self.emit("negq %rax");
+            if self.arithmetic.width == IntegerWidth::I64 {
+                self.emit(&format!("jno {}", done));
+                self.overflow(CalcTokenType::MINUS, node.op.span, &done, "movabsq $9223372036854775807, %rax");
+            }
+            self.fit(CalcTokenType::MINUS, node.op.span, &done);
+            self.place(&done);
+        }
+        Ok(())
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) -> Result<(), String> {
+        match self.arithmetic.literal(node.token.value.clone().unwrap_or(Value::UNIT)) {
+            Ok(Value::INT(n)) => self.emit(&format!("movabsq ${}, %rax", n)),
+            Ok(value) => return Err(format!("{} literals are not supported by the x86-64 backend", value.type_name())),
+            Err(code) => self.fail(code, node.token.span),
+        }
+        Ok(())
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) -> Result<(), String> {
+        if let AST::VAR(_) = &ast[node.left] {
+            let slot = self.program.slots[node.left].1;
+            self.text += &format!("    # line {}\n", node.op.span.line);
+            self.visit(ast, node.right)?;
+            self.emit(&format!("movq $1, main_frame+{}(%rip)", 16 * slot));
+            self.emit(&format!("movq %rax, main_frame+{}(%rip)", 16 * slot + 8));
+        }
+        Ok(())
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, id: NodeId, node: &Var) -> Result<(), String> {
+        let slot = self.program.slots[id].1;
+        let done = self.label();
+        self.emit(&format!("cmpq $0, main_frame+{}(%rip)", 16 * slot));
+        self.emit(&format!("jne {}", done));
+        self.fail(ErrorCode::UNDEFINEDVARIABLE(node.name()), node.token.span);
+        self.place(&done);
+        self.emit(&format!("movq main_frame+{}(%rip), %rax", 16 * slot + 8));
+        Ok(())
+    }
+}
+
+pub fn emit(program: &Program, mut arithmetic: Arithmetic) -> Result<String, String> {
+    if let Some(mode) = program.overflow {
+        arithmetic.overflow = mode;
+    }
+    if arithmetic.width == IntegerWidth::BIG {
+        return Err(String::from("arbitrary-precision integers are not supported by the x86-64 backend"));
+    }
+
+    let mut emitter = Emitter {
+        program,
+        arithmetic,
+        text: String::new(),
+        rodata: String::new(),
+        labels: 0,
+    };
+    emitter.text += "    .text\n    .globl main\nmain:\n";
+    emitter.emit("pushq %rbp");
+    emitter.emit("movq %rsp, %rbp");
+    emitter.visit(&program.ast, program.root)?;
+    emitter.emit("xorl %edi, %edi");
+    emitter.emit("call lsb_dump");
+    emitter.emit("xorl %eax, %eax");
+    emitter.emit("popq %rbp");
+    emitter.emit("ret");
+    emitter.dump();
+
+    let mut asm = String::from("# generated by lsbasi native\n");
+    asm += &emitter.text;
+    asm += RUNTIME;
+    asm += &emitter.rodata;
+    asm += &format!("\n    .bss\n    .align 16\nmain_frame:\n    .zero {}\n", 16 * program.globals.len().max(1));
+    asm += "\n    .section .note.GNU-stack,\"\",@progbits\n";
+    Ok(asm)
+}
+
+#[cfg(all(test, target_arch = "x86_64", target_os = "linux"))]
+mod tests {
+    use super::emit;
+    use crate::prepare;
+    use crate::tests::{ARITHMETICS, PROGRAMS, cc_output, options, output};
+
+    // PROGRAMS covers MIN / -1, MIN * -1 and -MIN at both widths, so every overflow mode checks those paths
+    #[test]
+    fn native_program_matches_interpreter() {
+        for &arithmetic in ARITHMETICS {
+            for text in PROGRAMS {
+                let options = options(arithmetic);
+                let program = prepare(&text.to_string(), &options).unwrap_or_else(|e| panic!("{}", e));
+                let source = emit(&program, arithmetic).unwrap();
+                let stdout = cc_output(&source, "s");
+                assert_eq!(stdout, output(text, &options), "{} with {:?}", text, arithmetic);
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use super::format;
+use super::visit::{VisitResult, VisitorMut, walk_binop_mut, walk_unaryop_mut};
+use super::{AST, ASTArena, Arithmetic, CalcTokenType, ErrorCode, NodeId, Num, RuntimeError, Token, Value};
+
+fn operand(ast: &ASTArena, id: NodeId, arithmetic: Arithmetic) -> Result<Value, RuntimeError> {
+    match &ast[id] {
+        AST::NUM(num) => arithmetic
+            .literal(num.token.value.clone().unwrap_or(Value::UNIT))
+            .map_err(|code| RuntimeError::new(code, num.token.span)),
+        _ => Ok(Value::UNIT),
+    }
+}
+
+fn reduce(ast: &mut ASTArena, id: NodeId, arithmetic: Arithmetic) -> Result<(), RuntimeError> {
+    let (value, span) = match &ast[id] {
+        AST::BINOP(bin_op) => {
+            let left = operand(ast, bin_op.left, arithmetic)?;
+            let right = operand(ast, bin_op.right, arithmetic)?;
+            (left.arith(bin_op.op.genre, &right, arithmetic), bin_op.op.span)
+        },
+        AST::UNARYOP(unary_op) => {
+            let value = operand(ast, unary_op.expr, arithmetic)?;
+            let result = match unary_op.op.genre {
+                CalcTokenType::MINUS => value.neg(arithmetic),
+                _ => value.pos(),
+            };
+            (result, unary_op.op.span)
+        },
+        _ => return Ok(()),
+    };
+    let value = value.map_err(|code| RuntimeError::new(code, span))?;
+    ast[id] = AST::NUM(Num::new(Token { genre: CalcTokenType::INTEGER, value: Some(value), span, trivia: Vec::new() }));
+    Ok(())
+}
+
+// rewrites the leftmost operator whose operands are all literals, answering Ok(false) once
+// the expression is a literal itself
+struct Reducer {
+    arithmetic: Arithmetic,
+}
+
+impl Reducer {
+    fn new(arithmetic: Arithmetic) -> Reducer {
+        Reducer {
+            arithmetic,
+        }
+    }
+}
+
+// Ok(true) ends a walk as well, once a rewrite has been made
+impl VisitResult for Result<bool, RuntimeError> {
+    fn output() -> Self {
+        Ok(false)
+    }
+
+    fn branch(&self) -> bool {
+        !matches!(self, Ok(false))
+    }
+}
+
+impl VisitorMut for Reducer {
+    type Result = Result<bool, RuntimeError>;
+
+    fn visit_binop_mut(&mut self, ast: &mut ASTArena, id: NodeId) -> Result<bool, RuntimeError> {
+        match walk_binop_mut(self, ast, id) {
+            Ok(false) => reduce(ast, id, self.arithmetic).map(|_| true),
+            result => result,
+        }
+    }
+
+    fn visit_unaryop_mut(&mut self, ast: &mut ASTArena, id: NodeId) -> Result<bool, RuntimeError> {
+        match walk_unaryop_mut(self, ast, id) {
+            Ok(false) => reduce(ast, id, self.arithmetic).map(|_| true),
+            result => result,
+        }
+    }
+
+    fn visit_var_mut(&mut self, ast: &mut ASTArena, id: NodeId) -> Result<bool, RuntimeError> {
+        match &ast[id] {
+            AST::VAR(var) => Err(RuntimeError::new(ErrorCode::UNDEFINEDVARIABLE(var.name()), var.token.span)),
+            _ => Ok(false),
+        }
+    }
+}
+
+// records the expression after every single reduction, starting with the expression as written
+pub fn explain(ast: &mut ASTArena, root: NodeId, arithmetic: Arithmetic, steps: &mut Vec<String>) -> Result<(), RuntimeError> {
+    steps.push(format::statement(ast, root));
+    let mut reducer = Reducer::new(arithmetic);
+    while reducer.visit_mut(ast, root)? {
+        let step = format::statement(ast, root);
+        // -3 reads the same before and after the negation is applied
+        if steps.last() != Some(&step) {
+            steps.push(step);
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::explain;
+    use crate::tests::ARITHMETICS;
+    use crate::{Arithmetic, Lexer, Parser};
+
+    // the steps :explain prints, and the error it ends with
+    fn explained(text: &str, arithmetic: Arithmetic) -> (Vec<String>, Option<String>) {
+        let text = text.to_string();
+        let mut parser = Parser::new(Lexer::new(&text));
+        let root = match parser.parse_expr() {
+            Ok(root) => root,
+            Err(e) => return (Vec::new(), Some(format!("{:?}", e))),
+        };
+        let mut steps = Vec::new();
+        let result = explain(&mut parser.ast, root, arithmetic, &mut steps);
+        (steps, result.err().map(|e| e.to_string()))
+    }
+
+    #[test]
+    fn innermost_operator_first() {
+        let (steps, error) = explained("(1 + 2) * 3 - 4 / -2", Arithmetic::new());
+        assert_eq!(steps, ["(1 + 2) * 3 - 4 / -2", "3 * 3 - 4 / -2", "9 - 4 / -2", "9 - -2", "11"]);
+        assert_eq!(error, None);
+        assert_eq!(explained("-(-3) * 2", Arithmetic::new()).0, ["- -3 * 2", "3 * 2", "6"]);
+    }
+
+    #[test]
+    fn errors_stop_after_the_last_step() {
+        let (steps, error) = explained("5 - 1 / (2 - 2)", Arithmetic::new());
+        assert_eq!(steps, ["5 - 1 / (2 - 2)", "5 - 1 / 0"]);
+        assert_eq!(error.unwrap(), "Runtime error at line 1, column 7: Division by zero");
+        for &arithmetic in ARITHMETICS {
+            let (_, error) = explained("x + 1", arithmetic);
+            assert_eq!(error.unwrap(), "Runtime error at line 1, column 1: Undefined variable: x");
+        }
+    }
+
+    #[test]
+    fn unbalanced_input_is_rejected() {
+        assert_eq!(explained("2 + (3", Arithmetic::new()), (Vec::new(), Some(String::from("')'"))));
+        assert_eq!(explained("2 + 3)", Arithmetic::new()), (Vec::new(), Some(String::from("'Z'"))));
+    }
+}
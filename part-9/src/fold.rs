@@ -0,0 +1,149 @@
+use super::visit::{Fold, walk_binop_fold, walk_unaryop_fold};
+use super::{
+    AST, ASTArena, Arithmetic, BinOp, CalcTokenType, Diagnostic, ErrorCode, IntegerWidth, NodeId, Num, OverflowMode, Span,
+    Token, UnaryOp, Value,
+};
+
+struct ConstantFolder {
+    arithmetic: Arithmetic,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ConstantFolder {
+    fn new(arithmetic: Arithmetic) -> ConstantFolder {
+        ConstantFolder {
+            arithmetic,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn fold_tree(mut self, ast: &mut ASTArena, root: NodeId) -> (NodeId, Vec<Diagnostic>) {
+        let root = self.fold(ast, root);
+        (root, self.diagnostics)
+    }
+
+    fn constant(&self, node: &AST) -> Option<Value> {
+        match node {
+            AST::NUM(num) => self.arithmetic.literal(num.token.value.clone()?).ok(),
+            _ => None,
+        }
+    }
+
+    fn literal(value: Value, span: Span) -> AST {
+        AST::NUM(Num::new(Token { genre: CalcTokenType::INTEGER, value: Some(value), span, trivia: Vec::new() }))
+    }
+}
+
+impl Fold for ConstantFolder {
+    fn fold_binop(&mut self, ast: &mut ASTArena, id: NodeId, node: BinOp) -> NodeId {
+        let node = walk_binop_fold(self, ast, node);
+
+        let op = node.op.genre;
+        match (self.constant(&ast[node.left]), self.constant(&ast[node.right])) {
+            (Some(left), Some(right)) => {
+                if let Ok(value) = left.arith(op, &right, self.arithmetic) {
+                    ast[id] = ConstantFolder::literal(value, node.op.span);
+                    return id;
+                }
+            },
+            (_, Some(Value::INT(1))) if op == CalcTokenType::MUL => return node.left,
+            (Some(Value::INT(1)), _) if op == CalcTokenType::MUL => return node.right,
+            (_, Some(Value::INT(0))) if op == CalcTokenType::PLUS || op == CalcTokenType::MINUS => return node.left,
+            (Some(Value::INT(0)), _) if op == CalcTokenType::PLUS => return node.right,
+            _ => {},
+        }
+
+        if op == CalcTokenType::DIV && self.constant(&ast[node.right]).is_some_and(|value| value.as_real() == 0.0) {
+            self.diagnostics.push(Diagnostic {
+                span: node.op.span,
+                message: ErrorCode::DIVISIONBYZERO.to_string(),
+            });
+        }
+        ast[id] = AST::BINOP(node);
+        id
+    }
+
+    fn fold_unaryop(&mut self, ast: &mut ASTArena, id: NodeId, node: UnaryOp) -> NodeId {
+        let node = walk_unaryop_fold(self, ast, node);
+
+        if let Some(value) = self.constant(&ast[node.expr]) {
+            let result = match node.op.genre {
+                CalcTokenType::PLUS => value.pos(),
+                CalcTokenType::MINUS => value.neg(self.arithmetic),
+                op => Err(ErrorCode::INVALIDOPERATOR(op)),
+            };
+            if let Ok(value) = result {
+                ast[id] = ConstantFolder::literal(value, node.op.span);
+                return id;
+            }
+        }
+
+        // -(-x) only round-trips when negating the minimum value cannot trap or saturate
+        let exact = self.arithmetic.overflow == OverflowMode::WRAP || self.arithmetic.width == IntegerWidth::BIG;
+        if node.op.genre == CalcTokenType::MINUS && exact {
+            if let AST::UNARYOP(inner) = &ast[node.expr] {
+                if inner.op.genre == CalcTokenType::MINUS {
+                    return inner.expr;
+                }
+            }
+        }
+        ast[id] = AST::UNARYOP(node);
+        id
+    }
+}
+
+pub fn fold(ast: &mut ASTArena, root: NodeId, arithmetic: Arithmetic) -> (NodeId, Vec<Diagnostic>) {
+    ConstantFolder::new(arithmetic).fold_tree(ast, root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fold;
+    use crate::tests::output;
+    use crate::{Arithmetic, IntegerWidth, Options, OverflowMode, Program, notation};
+
+    // each statement of `text` in prefix notation after folding
+    fn folded(text: &str, arithmetic: Arithmetic) -> Vec<String> {
+        let mut program = Program::parse(&text.to_string()).unwrap_or_else(|e| panic!("{}", e));
+        let (root, diagnostics) = fold(&mut program.ast, program.root, arithmetic);
+        assert!(diagnostics.is_empty());
+        notation::translate(&program.ast, root, notation::lisp)
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        let statements = folded("BEGIN a := 2 * 3 + x * 1 - 0; b := 0 + -(-4) / 2; c := - -x END.", Arithmetic::new());
+        assert_eq!(statements, ["a := (+ 6 x)", "b := 2", "c := (- (- x))"]);
+    }
+
+    #[test]
+    fn double_negation_folds_when_exact() {
+        let wrap = Arithmetic { overflow: OverflowMode::WRAP, width: IntegerWidth::I64 };
+        let big = Arithmetic { overflow: OverflowMode::TRAP, width: IntegerWidth::BIG };
+        for arithmetic in [wrap, big] {
+            assert_eq!(folded("BEGIN c := - -x END.", arithmetic), ["c := x"], "{:?}", arithmetic);
+        }
+    }
+
+    #[test]
+    fn overflow_is_left_to_run() {
+        let i32 = Arithmetic { overflow: OverflowMode::TRAP, width: IntegerWidth::I32 };
+        assert_eq!(folded("BEGIN a := 2147483647 + 1 END.", i32), ["a := (+ 2147483647 1)"]);
+    }
+
+    #[test]
+    fn division_by_zero_is_reported() {
+        let text = "BEGIN a := 4 / (2 - 2) END.";
+        let mut program = Program::parse(&text.to_string()).unwrap_or_else(|e| panic!("{}", e));
+        let (root, diagnostics) = fold(&mut program.ast, program.root, Arithmetic::new());
+        let diagnostics: Vec<String> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+        assert_eq!(diagnostics, ["Warning at line 1, column 14: Division by zero"]);
+        assert_eq!(notation::translate(&program.ast, root, notation::lisp), ["a := (/ 4 0)"]);
+        // the warning does not stop the program, which fails where it divides
+        assert_eq!(
+            output(text, &Options::new()),
+            "Error when calculate expression: Runtime error at line 1, column 14: Division by zero\n\
+             CALL STACK (most recent call first)\n1: PROGRAM main\n",
+        );
+    }
+}
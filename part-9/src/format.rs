@@ -0,0 +1,239 @@
+use super::visit::Visitor;
+use super::{AST, ASTArena, Assign, BinOp, CalcTokenType, Compound, NodeId, Num, Program, Span, Trivia, UnaryOp, Value, Var, tokenize};
+
+const INDENT: &str = "    ";
+
+struct Formatter {
+    out: String,
+    line: String,
+    indent: usize,
+    precedence: usize,
+    trivia: Vec<(Span, Vec<Trivia>)>,
+    next: usize,
+}
+
+impl Formatter {
+    fn space(&mut self) {
+        if !self.line.is_empty() && !self.line.ends_with(' ') && !self.line.ends_with('(') {
+            self.line.push(' ');
+        }
+    }
+
+    fn newline(&mut self) {
+        if !self.line.is_empty() {
+            self.out += &INDENT.repeat(self.indent);
+            self.out += self.line.trim_end();
+            self.out.push('\n');
+            self.line.clear();
+        }
+    }
+
+    fn word(&mut self, span: Option<Span>, text: &str) {
+        if let Some(span) = span {
+            self.flush(span);
+        }
+        self.line += text;
+    }
+
+    // emits the comments of every source token up to and including the one at `span`
+    fn flush(&mut self, span: Span) {
+        while self.next < self.trivia.len() && self.trivia[self.next].0 <= span {
+            let trivia = std::mem::take(&mut self.trivia[self.next].1);
+            self.next += 1;
+
+            let mut own_line = self.out.is_empty() && self.line.is_empty();
+            for piece in trivia {
+                match piece {
+                    Trivia::WHITESPACE(text) => {
+                        let newlines = text.matches('\n').count();
+                        if newlines > 0 {
+                            own_line = true;
+                        }
+                        if newlines > 1 && self.line.is_empty() && !self.out.is_empty() && !self.out.ends_with("\n\n") {
+                            self.out.push('\n');
+                        }
+                    },
+                    Trivia::COMMENT(body) => {
+                        self.comment(&body, own_line);
+                        own_line = false;
+                    },
+                }
+            }
+        }
+    }
+
+    fn comment(&mut self, body: &str, own_line: bool) {
+        let text = format!("{{{}}}", body);
+        if !self.line.is_empty() {
+            self.space();
+            self.line += &text;
+            self.line.push(' ');
+        } else if own_line || self.out.is_empty() {
+            self.out += &INDENT.repeat(self.indent);
+            self.out += &text;
+            self.out.push('\n');
+        } else {
+            // a comment that followed the previous line's last token stays on that line
+            self.out.pop();
+            self.out.push(' ');
+            self.out += &text;
+            self.out.push('\n');
+        }
+    }
+}
+
+impl Visitor for Formatter {
+    type Result = ();
+
+    fn visit_compound(&mut self, ast: &ASTArena, _id: NodeId, node: &Compound) {
+        self.word(Some(node.begin), "BEGIN");
+        self.newline();
+        self.indent += 1;
+        for (i, child) in node.children.iter().enumerate() {
+            self.precedence = 0;
+            self.visit(ast, *child);
+            if i + 1 < node.children.len() {
+                self.word(None, ";");
+            }
+            self.newline();
+        }
+        // comments left before END belong to the block body
+        self.flush(node.end);
+        self.indent -= 1;
+        self.word(Some(node.end), "END");
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) {
+        self.visit(ast, node.left);
+        self.space();
+        self.word(Some(node.op.span), ":=");
+        self.space();
+        self.precedence = 0;
+        self.visit(ast, node.right);
+    }
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) {
+        let own = match node.op.genre {
+            CalcTokenType::PLUS | CalcTokenType::MINUS => 1,
+            _ => 2,
+        };
+        let parenthesize = own < self.precedence;
+        if parenthesize {
+            self.word(None, "(");
+        }
+        self.precedence = own;
+        self.visit(ast, node.left);
+        self.space();
+        self.word(Some(node.op.span), &node.op.value.clone().unwrap_or(Value::UNIT).to_string());
+        self.space();
+        self.precedence = own + 1;
+        self.visit(ast, node.right);
+        if parenthesize {
+            self.word(None, ")");
+        }
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) {
+        self.word(Some(node.op.span), &node.op.value.clone().unwrap_or(Value::UNIT).to_string());
+        // - -a rather than --a, including once explain has folded the inner sign into a literal
+        let signed = match &ast[node.expr] {
+            AST::UNARYOP(_) => true,
+            AST::NUM(num) => num.token.value.clone().unwrap_or(Value::UNIT).to_string().starts_with('-'),
+            _ => false,
+        };
+        if signed {
+            self.line.push(' ');
+        }
+        self.precedence = 3;
+        self.visit(ast, node.expr);
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) {
+        self.word(Some(node.token.span), &node.token.value.clone().unwrap_or(Value::UNIT).to_string());
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, _id: NodeId, node: &Var) {
+        self.word(Some(node.token.span), &node.name());
+    }
+}
+
+// a single statement on one line, without comments
+pub fn statement(ast: &ASTArena, id: NodeId) -> String {
+    let mut formatter = Formatter {
+        out: String::new(),
+        line: String::new(),
+        indent: 0,
+        precedence: 0,
+        trivia: Vec::new(),
+        next: 0,
+    };
+    formatter.visit(ast, id);
+    formatter.line
+}
+
+pub fn format(program: &Program, text: &String) -> String {
+    let trivia = tokenize(text).into_iter().map(|token| (token.span, token.trivia)).collect();
+
+    let mut formatter = Formatter {
+        out: String::new(),
+        line: String::new(),
+        indent: 0,
+        precedence: 0,
+        trivia,
+        next: 0,
+    };
+    formatter.visit(&program.ast, program.root);
+    formatter.word(None, ".");
+    formatter.newline();
+    formatter.flush(Span { line: usize::MAX, column: usize::MAX });
+    formatter.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+    use crate::{Options, Program, fmt_text};
+
+    fn fmt(text: &str) -> String {
+        let program = Program::parse(&text.to_string()).unwrap_or_else(|e| panic!("{}", e));
+        format(&program, &text.to_string())
+    }
+
+    #[test]
+    fn canonical_layout() {
+        assert_eq!(
+            fmt("BEGIN a:=2;b:=a*(3+4)/-2 ; BEGIN c:=b-a END END."),
+            "BEGIN\n    a := 2;\n    b := a * (3 + 4) / -2;\n    BEGIN\n        c := b - a\n    END\nEND.\n",
+        );
+    }
+
+    #[test]
+    fn nested_signs_stay_apart() {
+        assert_eq!(fmt("BEGIN a := -(-3) * 2; b := +-a; c := 1 - -a END."), "BEGIN\n    a := - -3 * 2;\n    b := + -a;\n    c := 1 - -a\nEND.\n");
+        assert_eq!(fmt("BEGIN a := - - 3 END."), fmt(&fmt("BEGIN a := - - 3 END.")));
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_kept() {
+        let text = "{ header }\nBEGIN\n    a := 1; { one }\n\n    { before b }\n    b := a + 1\n    { trailing }\nEND.\n";
+        assert_eq!(fmt(text), text);
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for text in crate::tests::PROGRAMS {
+            let once = fmt(text);
+            assert_eq!(fmt(&once), once, "{}", text);
+        }
+    }
+
+    #[test]
+    fn check_fails_only_on_unformatted_input() {
+        let mut options = Options::new();
+        options.check = true;
+        let formatted = String::from("BEGIN\n    a := 1\nEND.\n");
+        assert_eq!(fmt_text("a.pas", &formatted, &options), Ok(formatted.clone()));
+        assert_eq!(fmt_text("b.pas", &String::from("BEGIN a := 1 END."), &options), Err(String::from("b.pas is not formatted")));
+        assert!(fmt_text("c.pas", &String::from("BEGIN a := END."), &options).is_err());
+    }
+}
@@ -0,0 +1,452 @@
+use std::fmt::Display;
+
+use super::{
+    AST, ASTArena, Arithmetic, Assign, BinOp, CalcTokenType, Compound, NoOp, NodeId, Num, OverflowMode, Program, Span,
+    UnaryOp, Value, Var,
+    visit::{VisitResult, Visitor, walk_compound},
+};
+
+const MAX_ROUNDS: usize = 16;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Operand {
+    CONST(Value),
+    VAR(usize, usize),
+    TEMP(usize),
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Instr {
+    COPY(Operand, Operand),
+    UNARY(Operand, CalcTokenType, Operand),
+    BINARY(Operand, CalcTokenType, Operand, Operand),
+    STEP,
+}
+
+impl Instr {
+    fn dest(&self) -> Option<&Operand> {
+        match self {
+            Instr::COPY(dest, _) | Instr::UNARY(dest, ..) | Instr::BINARY(dest, ..) => Some(dest),
+            Instr::STEP => None,
+        }
+    }
+
+    fn uses(&self) -> Vec<&Operand> {
+        match self {
+            Instr::COPY(_, source) | Instr::UNARY(_, _, source) => vec![source],
+            Instr::BINARY(_, _, left, right) => vec![left, right],
+            Instr::STEP => Vec::new(),
+        }
+    }
+
+    fn uses_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instr::COPY(_, source) | Instr::UNARY(_, _, source) => vec![source],
+            Instr::BINARY(_, _, left, right) => vec![left, right],
+            Instr::STEP => Vec::new(),
+        }
+    }
+
+    fn expression(&self) -> Option<(CalcTokenType, Vec<Operand>)> {
+        match self {
+            Instr::UNARY(_, op, operand) => Some((*op, vec![operand.clone()])),
+            Instr::BINARY(_, op, left, right) => Some((*op, vec![left.clone(), right.clone()])),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Pass {
+    FOLD,
+    COPY,
+    CSE,
+    DCE,
+}
+
+impl Pass {
+    pub fn parse(text: &str) -> Option<Pass> {
+        match text {
+            "fold" => Some(Pass::FOLD),
+            "copy" => Some(Pass::COPY),
+            "cse" => Some(Pass::CSE),
+            "dce" => Some(Pass::DCE),
+            _ => None,
+        }
+    }
+
+    pub fn all() -> Vec<Pass> {
+        vec![Pass::FOLD, Pass::COPY, Pass::CSE, Pass::DCE]
+    }
+
+    fn run(&self, block: &mut Block, arithmetic: Arithmetic) -> bool {
+        match self {
+            Pass::FOLD => fold(block, arithmetic),
+            Pass::COPY => propagate_copies(block),
+            Pass::CSE => eliminate_common_subexpressions(block),
+            Pass::DCE => eliminate_dead_code(block, arithmetic),
+        }
+    }
+}
+
+pub struct Block {
+    pub label: String,
+    pub code: Vec<Instr>,
+    pub spans: Vec<Option<Span>>,
+    // where each operand of an instruction was read, in the order of `Instr::uses`
+    pub sources: Vec<Vec<Option<Span>>>,
+}
+
+impl Block {
+    fn new(label: &str) -> Block {
+        Block {
+            label: label.to_string(),
+            code: Vec::new(),
+            spans: Vec::new(),
+            sources: Vec::new(),
+        }
+    }
+}
+
+pub struct Function {
+    pub names: Vec<String>,
+    pub overflow: Option<OverflowMode>,
+    pub temps: usize,
+    pub blocks: Vec<Block>,
+}
+
+impl Function {
+    pub fn optimize(&mut self, passes: &[Pass], mut arithmetic: Arithmetic) {
+        if let Some(mode) = self.overflow {
+            arithmetic.overflow = mode;
+        }
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for block in &mut self.blocks {
+                for pass in passes {
+                    changed |= pass.run(block, arithmetic);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    fn operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::CONST(value) => value.to_string(),
+            Operand::VAR(0, slot) => self.names[*slot].clone(),
+            Operand::VAR(depth, slot) => format!("{}:{}", depth, slot),
+            Operand::TEMP(n) => format!("t{}", n),
+        }
+    }
+
+    fn instr(&self, instr: &Instr) -> String {
+        match instr {
+            Instr::COPY(dest, source) => format!("{} = {}", self.operand(dest), self.operand(source)),
+            Instr::UNARY(dest, op, operand) => format!("{} = {}{}", self.operand(dest), symbol(*op), self.operand(operand)),
+            Instr::BINARY(dest, op, left, right) => {
+                format!("{} = {} {} {}", self.operand(dest), self.operand(left), symbol(*op), self.operand(right))
+            },
+            Instr::STEP => String::from("step"),
+        }
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for block in &self.blocks {
+            writeln!(f, "{}:", block.label)?;
+            for (i, (instr, span)) in block.code.iter().zip(&block.spans).enumerate() {
+                let line = match span {
+                    Some(span) => format!("{}:{}", span.line, span.column),
+                    None => String::from("-"),
+                };
+                writeln!(f, "    {:04}  {:>7}  {}", i, line, self.instr(instr))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn symbol(op: CalcTokenType) -> &'static str {
+    match op {
+        CalcTokenType::PLUS => "+",
+        CalcTokenType::MINUS => "-",
+        CalcTokenType::MUL => "*",
+        _ => "/",
+    }
+}
+
+struct Lowering<'a> {
+    program: &'a Program,
+    block: Block,
+    temps: usize,
+    // where the next BINOP or UNARYOP stores its result, instead of a fresh temp
+    target: Option<Operand>,
+}
+
+// the operand holding the value of a node and the span an error reading it is reported at
+impl VisitResult for (Operand, Option<Span>) {
+    fn output() -> Self {
+        (Operand::CONST(Value::UNIT), None)
+    }
+
+    fn branch(&self) -> bool {
+        false
+    }
+}
+
+impl<'a> Lowering<'a> {
+    fn new(program: &'a Program) -> Lowering<'a> {
+        Lowering {
+            program,
+            block: Block::new("entry"),
+            temps: 0,
+            target: None,
+        }
+    }
+
+    fn emit(&mut self, instr: Instr, span: Option<Span>, sources: Vec<Option<Span>>) {
+        self.block.code.push(instr);
+        self.block.spans.push(span);
+        self.block.sources.push(sources);
+    }
+
+    fn temp(&mut self) -> Operand {
+        self.temps += 1;
+        Operand::TEMP(self.temps - 1)
+    }
+
+    fn dest(&mut self) -> Operand {
+        match self.target.take() {
+            Some(target) => target,
+            None => self.temp(),
+        }
+    }
+
+    fn var(&self, id: NodeId) -> Operand {
+        let (depth, slot) = self.program.slots[id];
+        Operand::VAR(depth, slot)
+    }
+}
+
+impl<'a> Visitor for Lowering<'a> {
+    type Result = (Operand, Option<Span>);
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) -> (Operand, Option<Span>) {
+        let dest = self.dest();
+        let (left, left_span) = self.visit(ast, node.left);
+        let (right, right_span) = self.visit(ast, node.right);
+        let span = Some(node.op.span);
+        self.emit(Instr::BINARY(dest.clone(), node.op.genre, left, right), span, vec![left_span, right_span]);
+        (dest, span)
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) -> (Operand, Option<Span>) {
+        (Operand::CONST(node.token.value.clone().unwrap_or(Value::UNIT)), Some(node.token.span))
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) -> (Operand, Option<Span>) {
+        let dest = self.dest();
+        let (operand, operand_span) = self.visit(ast, node.expr);
+        let span = Some(node.op.span);
+        self.emit(Instr::UNARY(dest.clone(), node.op.genre, operand), span, vec![operand_span]);
+        (dest, span)
+    }
+
+    fn visit_compound(&mut self, ast: &ASTArena, _id: NodeId, node: &Compound) -> (Operand, Option<Span>) {
+        self.emit(Instr::STEP, None, Vec::new());
+        walk_compound(self, ast, node)
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) -> (Operand, Option<Span>) {
+        self.emit(Instr::STEP, Some(node.op.span), Vec::new());
+        if let AST::VAR(_) = &ast[node.left] {
+            let dest = self.var(node.left);
+            if matches!(&ast[node.right], AST::BINOP(_) | AST::UNARYOP(_)) {
+                // the operator writes straight to the variable
+                self.target = Some(dest);
+                self.visit(ast, node.right);
+            } else {
+                let (source, span) = self.visit(ast, node.right);
+                self.emit(Instr::COPY(dest, source), Some(node.op.span), vec![span]);
+            }
+        }
+        VisitResult::output()
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, id: NodeId, node: &Var) -> (Operand, Option<Span>) {
+        (self.var(id), Some(node.token.span))
+    }
+
+    fn visit_noop(&mut self, _ast: &ASTArena, _id: NodeId, _node: &NoOp) -> (Operand, Option<Span>) {
+        self.emit(Instr::STEP, None, Vec::new());
+        VisitResult::output()
+    }
+}
+
+pub fn lower(program: &Program) -> Function {
+    let mut lowering = Lowering::new(program);
+    lowering.visit(&program.ast, program.root);
+    Function {
+        names: program.globals.clone(),
+        overflow: program.overflow,
+        temps: lowering.temps,
+        blocks: vec![lowering.block],
+    }
+}
+
+fn evaluate(instr: &Instr, arithmetic: Arithmetic) -> Option<Value> {
+    match instr {
+        Instr::UNARY(_, op, Operand::CONST(value)) => {
+            let value = arithmetic.literal(value.clone()).ok()?;
+            match op {
+                CalcTokenType::PLUS => value.pos().ok(),
+                CalcTokenType::MINUS => value.neg(arithmetic).ok(),
+                _ => None,
+            }
+        },
+        Instr::BINARY(_, op, Operand::CONST(left), Operand::CONST(right)) => {
+            let left = arithmetic.literal(left.clone()).ok()?;
+            let right = arithmetic.literal(right.clone()).ok()?;
+            left.arith(*op, &right, arithmetic).ok()
+        },
+        _ => None,
+    }
+}
+
+fn fold(block: &mut Block, arithmetic: Arithmetic) -> bool {
+    let mut changed = false;
+    let mut constants: Vec<(Operand, Value)> = Vec::new();
+    for ((instr, span), sources) in block.code.iter_mut().zip(&block.spans).zip(&mut block.sources) {
+        for operand in instr.uses_mut() {
+            if let Some((_, value)) = constants.iter().find(|(place, _)| place == operand) {
+                *operand = Operand::CONST(value.clone());
+                changed = true;
+            }
+        }
+
+        if let Some(value) = evaluate(instr, arithmetic) {
+            *instr = Instr::COPY(instr.dest().unwrap().clone(), Operand::CONST(value));
+            *sources = vec![*span];
+            changed = true;
+        }
+
+        if let Some(dest) = instr.dest() {
+            constants.retain(|(place, _)| place != dest);
+            if let Instr::COPY(dest, Operand::CONST(value)) = instr {
+                if arithmetic.literal(value.clone()).is_ok() {
+                    constants.push((dest.clone(), value.clone()));
+                }
+            }
+        }
+    }
+    changed
+}
+
+fn propagate_copies(block: &mut Block) -> bool {
+    let mut changed = false;
+    let mut copies: Vec<(Operand, Operand, Option<Span>)> = Vec::new();
+    for (instr, sources) in block.code.iter_mut().zip(&mut block.sources) {
+        for (operand, span) in instr.uses_mut().into_iter().zip(sources.iter_mut()) {
+            if let Some((_, source, source_span)) = copies.iter().find(|(dest, ..)| dest == operand) {
+                *operand = source.clone();
+                *span = *source_span;
+                changed = true;
+            }
+        }
+
+        if let Some(dest) = instr.dest() {
+            copies.retain(|(place, source, _)| place != dest && source != dest);
+            if let Instr::COPY(dest, source) = instr {
+                if !matches!(source, Operand::CONST(_)) && source != dest {
+                    copies.push((dest.clone(), source.clone(), sources[0]));
+                }
+            }
+        }
+    }
+    changed
+}
+
+fn eliminate_common_subexpressions(block: &mut Block) -> bool {
+    let mut changed = false;
+    let mut available: Vec<((CalcTokenType, Vec<Operand>), Operand)> = Vec::new();
+    for ((instr, span), sources) in block.code.iter_mut().zip(&block.spans).zip(&mut block.sources) {
+        let expression = instr.expression();
+        if let Some((op, operands)) = &expression {
+            let mut swapped = operands.clone();
+            swapped.reverse();
+            let commutative = *op == CalcTokenType::PLUS || *op == CalcTokenType::MUL;
+            let previous = available.iter().find(|((other, args), _)| {
+                other == op && (args == operands || (commutative && *args == swapped))
+            });
+            if let Some((_, previous)) = previous.filter(|(_, place)| Some(place) != instr.dest()) {
+                *instr = Instr::COPY(instr.dest().unwrap().clone(), previous.clone());
+                *sources = vec![*span];
+                changed = true;
+            }
+        }
+
+        if let Some(dest) = instr.dest() {
+            available.retain(|((_, operands), place)| place != dest && !operands.contains(dest));
+            if let Some((op, operands)) = expression {
+                if !operands.contains(dest) && instr.expression().is_some() {
+                    available.push(((op, operands), dest.clone()));
+                }
+            }
+        }
+    }
+    changed
+}
+
+fn can_fail(instr: &Instr, arithmetic: Arithmetic) -> bool {
+    match instr {
+        Instr::COPY(_, Operand::CONST(value)) => arithmetic.literal(value.clone()).is_err(),
+        Instr::COPY(_, Operand::TEMP(_)) => false,
+        _ => true,
+    }
+}
+
+fn eliminate_dead_code(block: &mut Block, arithmetic: Arithmetic) -> bool {
+    // every variable is observable once the program ends, temporaries only through their uses
+    let mut dead: Vec<Operand> = Vec::new();
+    let mut live: Vec<Operand> = Vec::new();
+    let mut keep = vec![true; block.code.len()];
+    for (i, instr) in block.code.iter().enumerate().rev() {
+        if let Some(dest) = instr.dest() {
+            let needed = match dest {
+                Operand::TEMP(_) => live.contains(dest),
+                _ => !dead.contains(dest),
+            };
+            if !needed && !can_fail(instr, arithmetic) {
+                keep[i] = false;
+                continue;
+            }
+            match dest {
+                Operand::TEMP(_) => live.retain(|place| place != dest),
+                _ => dead.push(dest.clone()),
+            }
+        }
+        // a runtime error here dumps the variables, so every earlier store is observable
+        if can_fail(instr, arithmetic) {
+            dead.clear();
+        }
+        for operand in instr.uses() {
+            match operand {
+                Operand::TEMP(_) => live.push(operand.clone()),
+                Operand::VAR(..) => dead.retain(|place| place != operand),
+                Operand::CONST(_) => {},
+            }
+        }
+    }
+
+    let mut flags = keep.iter();
+    block.code.retain(|_| *flags.next().unwrap());
+    let mut flags = keep.iter();
+    block.spans.retain(|_| *flags.next().unwrap());
+    let mut flags = keep.iter();
+    block.sources.retain(|_| *flags.next().unwrap());
+    keep.contains(&false)
+}
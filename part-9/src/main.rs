@@ -1,6 +1,31 @@
-use std::{io::{self, Write}, fmt::{Display}, collections::HashMap};
-
-#[derive(PartialEq, Copy, Clone)]
+#![allow(clippy::upper_case_acronyms)]
+
+use std::{io::{self, Write}, fmt::{Display}, collections::HashMap, time::{Duration, Instant}};
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+use serde::Serialize;
+
+mod annotate;
+mod bytecode;
+mod emit_c;
+mod emit_wat;
+mod emit_x86;
+mod explain;
+mod fold;
+mod format;
+mod ir;
+mod notation;
+mod parse_trace;
+mod parse_tree;
+mod sexp;
+mod trace;
+mod visit;
+mod visualize;
+
+use trace::TraceStep;
+use visit::{VisitResult, Visitor};
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize)]
 enum TokenType {
     INTEGER,
     EOF,
@@ -41,23 +66,425 @@ impl Display for CalcTokenType {
     }
 }
 
-#[derive(Hash, Eq, PartialEq)]
+#[derive(PartialEq, Clone, Debug, Serialize)]
 enum Value {
     CHAR(char),
-    INT(i32),
+    INT(i64),
+    BIGINT(#[serde(serialize_with = "serialize_bigint")] BigInt),
+    REAL(f64),
+    BOOLEAN(bool),
     STRING(String),
+    // placeholders until the language has arrays, records and pointers to construct them
+    #[allow(dead_code)]
+    ARRAY(Vec<Value>),
+    #[allow(dead_code)]
+    RECORD(Vec<(String, Value)>),
+    #[allow(dead_code)]
+    POINTER(Option<usize>),
+    UNIT,
+}
+
+fn serialize_bigint<S: serde::Serializer>(value: &BigInt, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+        match self {
+            Value::CHAR(c) => write!(f, "{}", c),
+            Value::INT(i) => write!(f, "{}", i),
+            Value::BIGINT(i) => write!(f, "{}", i),
+            Value::REAL(r) => write!(f, "{:?}", r),
+            Value::BOOLEAN(b) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            Value::STRING(s) => write!(f, "{}", s),
+            Value::ARRAY(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Value::RECORD(fields) => {
+                write!(f, "(")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}: {}", name, value)?;
+                }
+                write!(f, ")")
+            },
+            Value::POINTER(Some(addr)) => write!(f, "^{:#x}", addr),
+            Value::POINTER(None) => write!(f, "NIL"),
+            Value::UNIT => write!(f, "()"),
+        }
+    }
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::CHAR(_) => "CHAR",
+            Value::INT(_) | Value::BIGINT(_) => "INTEGER",
+            Value::REAL(_) => "REAL",
+            Value::BOOLEAN(_) => "BOOLEAN",
+            Value::STRING(_) => "STRING",
+            Value::ARRAY(_) => "ARRAY",
+            Value::RECORD(_) => "RECORD",
+            Value::POINTER(_) => "POINTER",
+            Value::UNIT => "UNIT",
+        }
+    }
+
+    fn arith(&self, op: CalcTokenType, other: &Value, arithmetic: Arithmetic) -> Result<Value, ErrorCode> {
+        match (self, other) {
+            (Value::INT(a), Value::INT(b)) => arithmetic.int_binop(op, *a, *b),
+            (Value::INT(_) | Value::BIGINT(_), Value::INT(_) | Value::BIGINT(_)) => {
+                arithmetic.big_binop(op, &self.as_bigint(), &other.as_bigint())
+            },
+            (Value::INT(_) | Value::BIGINT(_) | Value::REAL(_), Value::INT(_) | Value::BIGINT(_) | Value::REAL(_)) => {
+                let (a, b) = (self.as_real(), other.as_real());
+                match op {
+                    CalcTokenType::PLUS => Ok(Value::REAL(a + b)),
+                    CalcTokenType::MINUS => Ok(Value::REAL(a - b)),
+                    CalcTokenType::MUL => Ok(Value::REAL(a * b)),
+                    CalcTokenType::DIV if b == 0.0 => Err(ErrorCode::DIVISIONBYZERO),
+                    CalcTokenType::DIV => Ok(Value::REAL(a / b)),
+                    _ => Err(ErrorCode::INVALIDOPERATOR(op)),
+                }
+            },
+            (Value::STRING(_) | Value::CHAR(_), Value::STRING(_) | Value::CHAR(_)) if op == CalcTokenType::PLUS => {
+                Ok(Value::STRING(format!("{}{}", self, other)))
+            },
+            _ => Err(ErrorCode::type_mismatch(op, self, other)),
+        }
+    }
+
+    // the bytes the value holds on the heap; INTEGER, REAL and the like live in their slot
+    fn size(&self) -> usize {
+        let element = std::mem::size_of::<Value>();
+        match self {
+            Value::BIGINT(n) => (n.bits() as usize).div_ceil(8),
+            Value::STRING(s) => s.len(),
+            Value::ARRAY(items) => items.iter().map(|item| element + item.size()).sum(),
+            Value::RECORD(fields) => fields.iter().map(|(name, value)| name.len() + element + value.size()).sum(),
+            _ => 0,
+        }
+    }
+
+    fn as_real(&self) -> f64 {
+        match self {
+            Value::INT(a) => *a as f64,
+            Value::BIGINT(a) => a.to_f64().unwrap_or(f64::NAN),
+            Value::REAL(a) => *a,
+            _ => f64::NAN,
+        }
+    }
+
+    fn as_bigint(&self) -> BigInt {
+        match self {
+            Value::INT(a) => BigInt::from(*a),
+            Value::BIGINT(a) => a.clone(),
+            _ => BigInt::zero(),
+        }
+    }
+
+    fn neg(&self, arithmetic: Arithmetic) -> Result<Value, ErrorCode> {
+        match self {
+            Value::INT(a) => arithmetic.int_neg(*a),
+            Value::BIGINT(a) => Ok(Arithmetic::normalize(-a)),
+            Value::REAL(a) => Ok(Value::REAL(-a)),
+            _ => Err(ErrorCode::BADOPERAND { op: CalcTokenType::MINUS, operand: self.type_name() }),
+        }
+    }
+
+    fn pos(&self) -> Result<Value, ErrorCode> {
+        match self {
+            Value::INT(_) | Value::BIGINT(_) | Value::REAL(_) => Ok(self.clone()),
+            _ => Err(ErrorCode::BADOPERAND { op: CalcTokenType::PLUS, operand: self.type_name() }),
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Copy, Clone, Debug, Default, Serialize)]
+struct Span {
+    line: usize,
+    column: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum OverflowMode {
+    TRAP,
+    WRAP,
+    SATURATE,
+}
+
+impl OverflowMode {
+    fn parse(name: &str) -> Option<OverflowMode> {
+        match name {
+            "trap" => Some(OverflowMode::TRAP),
+            "wrap" => Some(OverflowMode::WRAP),
+            "saturate" => Some(OverflowMode::SATURATE),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum IntegerWidth {
+    I32,
+    I64,
+    BIG,
+}
+
+impl IntegerWidth {
+    fn parse(name: &str) -> Option<IntegerWidth> {
+        match name {
+            "i32" => Some(IntegerWidth::I32),
+            "i64" => Some(IntegerWidth::I64),
+            "big" => Some(IntegerWidth::BIG),
+            _ => None,
+        }
+    }
+
+    fn bounds(&self) -> (i128, i128) {
+        match self {
+            IntegerWidth::I32 => (i32::MIN as i128, i32::MAX as i128),
+            IntegerWidth::I64 | IntegerWidth::BIG => (i64::MIN as i128, i64::MAX as i128),
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+struct Arithmetic {
+    overflow: OverflowMode,
+    width: IntegerWidth,
+}
+
+impl Arithmetic {
+    fn new() -> Arithmetic {
+        Arithmetic {
+            overflow: OverflowMode::TRAP,
+            width: IntegerWidth::I64,
+        }
+    }
+
+    fn fit(&self, op: CalcTokenType, exact: i128) -> Result<Value, ErrorCode> {
+        let (min, max) = self.width.bounds();
+        if exact >= min && exact <= max {
+            return Ok(Value::INT(exact as i64));
+        }
+
+        match (self.width, self.overflow) {
+            (IntegerWidth::BIG, _) => Ok(Value::BIGINT(BigInt::from(exact))),
+            (_, OverflowMode::TRAP) => Err(ErrorCode::OVERFLOW(op)),
+            (IntegerWidth::I32, OverflowMode::WRAP) => Ok(Value::INT(exact as i32 as i64)),
+            (_, OverflowMode::WRAP) => Ok(Value::INT(exact as i64)),
+            (_, OverflowMode::SATURATE) => Ok(Value::INT(exact.clamp(min, max) as i64)),
+        }
+    }
+
+    fn normalize(n: BigInt) -> Value {
+        match n.to_i64() {
+            Some(i) => Value::INT(i),
+            None => Value::BIGINT(n),
+        }
+    }
+
+    fn int_binop(&self, op: CalcTokenType, a: i64, b: i64) -> Result<Value, ErrorCode> {
+        let (a, b) = (a as i128, b as i128);
+        let exact = match op {
+            CalcTokenType::PLUS => a + b,
+            CalcTokenType::MINUS => a - b,
+            CalcTokenType::MUL => a * b,
+            CalcTokenType::DIV => {
+                if b == 0 {
+                    return Err(ErrorCode::DIVISIONBYZERO);
+                }
+                a / b
+            },
+            _ => return Err(ErrorCode::INVALIDOPERATOR(op)),
+        };
+        self.fit(op, exact)
+    }
+
+    fn int_neg(&self, a: i64) -> Result<Value, ErrorCode> {
+        self.fit(CalcTokenType::MINUS, -(a as i128))
+    }
+
+    fn big_binop(&self, op: CalcTokenType, a: &BigInt, b: &BigInt) -> Result<Value, ErrorCode> {
+        let result = match op {
+            CalcTokenType::PLUS => a + b,
+            CalcTokenType::MINUS => a - b,
+            CalcTokenType::MUL => a * b,
+            CalcTokenType::DIV => {
+                if b.is_zero() {
+                    return Err(ErrorCode::DIVISIONBYZERO);
+                }
+                a / b
+            },
+            _ => return Err(ErrorCode::INVALIDOPERATOR(op)),
+        };
+        Ok(Arithmetic::normalize(result))
+    }
+
+    fn literal(&self, value: Value) -> Result<Value, ErrorCode> {
+        let (min, max) = self.width.bounds();
+        match value {
+            Value::INT(n) if (n as i128) < min || (n as i128) > max => {
+                Err(ErrorCode::OUTOFRANGE(n.to_string()))
+            },
+            Value::BIGINT(n) if self.width != IntegerWidth::BIG => Err(ErrorCode::OUTOFRANGE(n.to_string())),
+            _ => Ok(value),
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum Limit {
+    STEPS(u64),
+    DEPTH(usize),
+    MEMORY(usize),
+    TIMEOUT(Duration),
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::STEPS(n) => write!(f, "more than {} statements executed", n),
+            Limit::DEPTH(n) => write!(f, "call depth exceeds {}", n),
+            Limit::MEMORY(n) => write!(f, "more than {} bytes allocated", n),
+            Limit::TIMEOUT(t) => write!(f, "running time exceeds {} ms", t.as_millis()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Limits {
+    max_steps: Option<u64>,
+    max_depth: Option<usize>,
+    max_memory: Option<usize>,
+    timeout: Option<Duration>,
+}
+
+#[derive(Debug)]
+enum ErrorCode {
+    TYPEMISMATCH { op: CalcTokenType, left: &'static str, right: &'static str },
+    BADOPERAND { op: CalcTokenType, operand: &'static str },
+    INVALIDOPERATOR(CalcTokenType),
+    UNDEFINEDVARIABLE(String),
+    DIVISIONBYZERO,
+    OVERFLOW(CalcTokenType),
+    OUTOFRANGE(String),
+    LIMITEXCEEDED(Limit),
+    STACKUNDERFLOW,
+}
+
+impl ErrorCode {
+    fn type_mismatch(op: CalcTokenType, left: &Value, right: &Value) -> ErrorCode {
+        ErrorCode::TYPEMISMATCH { op, left: left.type_name(), right: right.type_name() }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorCode::TYPEMISMATCH { op, left, right } => {
+                write!(f, "Type error: unsupported operand types for {}: {} and {}", op, left, right)
+            },
+            ErrorCode::BADOPERAND { op, operand } => {
+                write!(f, "Type error: bad operand type for unary {}: {}", op, operand)
+            },
+            ErrorCode::INVALIDOPERATOR(op) => write!(f, "Invalid operator: {}", op),
+            ErrorCode::UNDEFINEDVARIABLE(name) => write!(f, "Undefined variable: {}", name),
+            ErrorCode::DIVISIONBYZERO => write!(f, "Division by zero"),
+            ErrorCode::OVERFLOW(op) => write!(f, "Integer overflow in {}", op),
+            ErrorCode::OUTOFRANGE(literal) => write!(f, "Integer literal {} is out of range", literal),
+            ErrorCode::LIMITEXCEEDED(limit) => write!(f, "Execution limit exceeded: {}", limit),
+            ErrorCode::STACKUNDERFLOW => write!(f, "Stack underflow"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RuntimeError {
+    code: ErrorCode,
+    span: Option<Span>,
+    stack: Vec<ActivationRecord>,
+}
+
+impl RuntimeError {
+    fn new(code: ErrorCode, span: Span) -> RuntimeError {
+        RuntimeError {
+            code,
+            span: Some(span),
+            stack: Vec::new(),
+        }
+    }
+
+    fn unspanned(code: ErrorCode) -> RuntimeError {
+        RuntimeError {
+            code,
+            span: None,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "Runtime error at {}: {}", span, self.code),
+            None => write!(f, "Runtime error: {}", self.code),
+        }
+    }
+}
+
+enum InterpretError {
+    SYNTAX(char, Span),
+    RUNTIME(RuntimeError),
+}
+
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::SYNTAX(code, span) => write!(f, "Invalid syntax at {}: {:?}", span, code),
+            InterpretError::RUNTIME(e) => {
+                write!(f, "{}", e)?;
+                if !e.stack.is_empty() {
+                    write!(f, "\nCALL STACK (most recent call first)")?;
+                    for record in e.stack.iter().rev() {
+                        write!(f, "\n{}", record)?;
+                    }
+                }
+                Ok(())
+            },
+        }
     }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Debug, Serialize)]
+enum Trivia {
+    WHITESPACE(String),
+    COMMENT(String),
+}
+
+#[derive(PartialEq, Clone, Serialize)]
 struct Token {
     genre: CalcTokenType,
     value: Option<Value>,
+    span: Span,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    trivia: Vec<Trivia>,
 }
 
 impl Display for Token {
@@ -69,15 +496,21 @@ impl Display for Token {
     }
 }
 
-const RESERVED_KEYWORDS: HashMap<&str, Token> = HashMap::from([
-    ("BEGIN", Token{genre: CalcTokenType::BEGIN, value: Some(Value::STRING(String::from("BEGIN")))}),
-    ("END", Token{genre: CalcTokenType::END, value: Some(Value::STRING(String::from("END")))}),
-]);
+fn reserved_keywords() -> HashMap<&'static str, Token> {
+    HashMap::from([
+        ("BEGIN", Token{genre: CalcTokenType::BEGIN, value: Some(Value::STRING(String::from("BEGIN"))), span: Span::default(), trivia: Vec::new()}),
+        ("END", Token{genre: CalcTokenType::END, value: Some(Value::STRING(String::from("END"))), span: Span::default(), trivia: Vec::new()}),
+    ])
+}
 
 struct Lexer<'a> {
     text: &'a String,
     pos: i32,
     current_char: Option<char>,
+    line: usize,
+    column: usize,
+    overflow: Option<OverflowMode>,
+    trivia: Vec<Trivia>,
 }
 
 impl<'a> Display for Lexer<'a> {
@@ -93,13 +526,23 @@ impl<'a> Lexer<'a> {
 
     fn new(text: &'a String)-> Lexer<'a> {
         Lexer {
-            text: &text,
+            text,
             pos: 0,
-            current_char: Some(text.chars().nth(0).unwrap()),
+            current_char: text.chars().nth(0),
+            line: 1,
+            column: 1,
+            overflow: None,
+            trivia: Vec::new(),
         }
     }
 
     fn advance(&mut self) {
+        if self.current_char == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         self.pos += 1;
         if self.pos > self.text.len() as i32 - 1 {
             self.current_char = None
@@ -109,9 +552,29 @@ impl<'a> Lexer<'a> {
     }
 
     fn skip_whitespace(&mut self) {
-        while self.current_char != None && self.current_char.unwrap().is_whitespace() {
+        let mut text = String::new();
+        while self.current_char.is_some() && self.current_char.unwrap().is_whitespace() {
+            text.push(self.current_char.unwrap());
+            self.advance();
+        }
+        self.trivia.push(Trivia::WHITESPACE(text));
+    }
+
+    fn skip_comment(&mut self) {
+        let mut body = String::new();
+        self.advance();
+        while self.current_char.is_some() && self.current_char.unwrap() != '}' {
+            body.push(self.current_char.unwrap());
             self.advance();
         }
+        self.advance();
+
+        self.trivia.push(Trivia::COMMENT(body.clone()));
+        match body.trim() {
+            "$Q+" => self.overflow = Some(OverflowMode::TRAP),
+            "$Q-" => self.overflow = Some(OverflowMode::WRAP),
+            _ => {},
+        }
     }
 
     fn peek(&self) -> Option<char> {
@@ -123,76 +586,190 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn integer(&mut self) -> i32 {
+    fn integer(&mut self) -> Value {
         let mut result = String::from("");
 
-        while self.current_char != None && self.current_char.unwrap().is_digit(10) {
+        while self.current_char.is_some() && self.current_char.unwrap().is_ascii_digit() {
             result.push(self.current_char.unwrap());
             self.advance();
         }
 
-        return result.parse::<i32>().unwrap();
+        match result.parse::<i64>() {
+            Ok(n) => Value::INT(n),
+            Err(_) => Value::BIGINT(result.parse::<BigInt>().unwrap_or_default()),
+        }
     }
 
-    fn _id(self) -> Token {
+    fn _id(&mut self) -> Token {
         let mut result = String::from("");
-        while self.current_char != None && self.current_char.unwrap().is_alphanumeric() {
+        while self.current_char.is_some() && self.current_char.unwrap().is_alphanumeric() {
             result.push(self.current_char.unwrap());
             self.advance();
         }
 
-        if RESERVED_KEYWORDS.contains_key(&result as &str) {
-            *RESERVED_KEYWORDS.get(&result as &str).unwrap()
+        let keywords = reserved_keywords();
+        if keywords.contains_key(&result as &str) {
+            keywords.get(&result as &str).unwrap().clone()
         } else {
-            Token { genre: CalcTokenType::ID, value: Some(Value::STRING(result)) }
+            Token { genre: CalcTokenType::ID, value: Some(Value::STRING(result)), span: Span::default(), trivia: Vec::new() }
         }
     }
 
+    // the parser's tokens end up in the AST, which leaves trivia to the formatter's own tokenize
     fn get_next_token(&mut self) -> Token {
+        let token = self.scan();
+        self.trivia.clear();
+        token
+    }
+
+    fn get_next_token_with_trivia(&mut self) -> Token {
+        let mut token = self.scan();
+        token.trivia = std::mem::take(&mut self.trivia);
+        token
+    }
+
+    fn scan(&mut self) -> Token {
         while let Some(current_char) = self.current_char {
+            let span = Span { line: self.line, column: self.column };
             if current_char.is_whitespace() {
                 self.skip_whitespace();
                 continue;
+            } else if current_char == '{' {
+                self.skip_comment();
+                continue;
             } else if current_char.is_alphabetic() {
-                return self._id();
-            } else if current_char.is_digit(10) {
-                return Token{ genre: CalcTokenType::INTEGER, value: Some(Value::INT(self.integer()))};
+                let mut token = self._id();
+                token.span = span;
+                return token;
+            } else if current_char.is_ascii_digit() {
+                return Token{ genre: CalcTokenType::INTEGER, value: Some(self.integer()), span, trivia: Vec::new() };
             } else if current_char == '*' {
                 self.advance();
-                return Token{ genre: CalcTokenType::MUL, value: Some(Value::CHAR('*')) };
+                return Token{ genre: CalcTokenType::MUL, value: Some(Value::CHAR('*')), span, trivia: Vec::new() };
             } else if current_char == '/' {
                 self.advance();
-                return Token{ genre: CalcTokenType::DIV, value: Some(Value::CHAR('/')) };
+                return Token{ genre: CalcTokenType::DIV, value: Some(Value::CHAR('/')), span, trivia: Vec::new() };
             } else if current_char == '+' {
                 self.advance();
-                return Token{ genre: CalcTokenType::PLUS, value: Some(Value::CHAR('+')) };
+                return Token{ genre: CalcTokenType::PLUS, value: Some(Value::CHAR('+')), span, trivia: Vec::new() };
             } else if current_char == '-' {
                 self.advance();
-                return Token{ genre: CalcTokenType::MINUS, value: Some(Value::CHAR('-')) };
+                return Token{ genre: CalcTokenType::MINUS, value: Some(Value::CHAR('-')), span, trivia: Vec::new() };
             } else if current_char == '(' {
                 self.advance();
-                return Token{ genre: CalcTokenType::LPAREN, value: Some(Value::CHAR('(')) };
+                return Token{ genre: CalcTokenType::LPAREN, value: Some(Value::CHAR('(')), span, trivia: Vec::new() };
             } else if current_char == ')' {
                 self.advance();
-                return Token{ genre: CalcTokenType::RPAREN, value: Some(Value::CHAR(')')) };
+                return Token{ genre: CalcTokenType::RPAREN, value: Some(Value::CHAR(')')), span, trivia: Vec::new() };
             } else if current_char == '.' {
                 self.advance();
-                return Token{ genre: CalcTokenType::DOT, value: Some(Value::CHAR('.')) };} else if current_char == ';' {self.advance();
-                return Token{ genre: CalcTokenType::SEMI, value: Some(Value::CHAR(';')) };
-            } else if current_char == ':' && self.peek().unwrap() == '=' {
+                return Token{ genre: CalcTokenType::DOT, value: Some(Value::CHAR('.')), span, trivia: Vec::new() };
+            } else if current_char == ';' {
                 self.advance();
+                return Token{ genre: CalcTokenType::SEMI, value: Some(Value::CHAR(';')), span, trivia: Vec::new() };
+            } else if current_char == ':' && self.peek() == Some('=') {
                 self.advance();
-                return Token{ genre: CalcTokenType::ASSIGN, value: Some(Value::STRING(String::from(":="))) };
+                self.advance();
+                return Token{ genre: CalcTokenType::ASSIGN, value: Some(Value::STRING(String::from(":="))), span, trivia: Vec::new() };
             } else {
-                println!("Failed to parse input word:{}", current_char);
-                return Token{genre: CalcTokenType::EOF, value: None};
+                // an unknown character ends the input, but the token keeps it so the parser can reject it
+                return Token{genre: CalcTokenType::EOF, value: Some(Value::CHAR(current_char)), span, trivia: Vec::new() };
             }
         }
 
-        return Token{genre: CalcTokenType::EOF, value: None};
+        Token{genre: CalcTokenType::EOF, value: None, span: Span { line: self.line, column: self.column }, trivia: Vec::new() }
+    }
+}
+
+fn tokenize(text: &String) -> Vec<Token> {
+    let mut lexer = Lexer::new(text);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.get_next_token_with_trivia();
+        let eof = token.genre == CalcTokenType::EOF;
+        tokens.push(token);
+        if eof {
+            return tokens;
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize)]
+struct NodeId(usize);
+
+#[derive(Serialize)]
+struct ASTArena {
+    nodes: Vec<AST>,
+}
+
+impl ASTArena {
+    fn new() -> ASTArena {
+        ASTArena {
+            nodes: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, node: AST) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() - 1)
+    }
+}
+
+impl std::ops::Index<NodeId> for ASTArena {
+    type Output = AST;
+
+    fn index(&self, id: NodeId) -> &AST {
+        &self.nodes[id.0]
+    }
+}
+
+impl std::ops::IndexMut<NodeId> for ASTArena {
+    fn index_mut(&mut self, id: NodeId) -> &mut AST {
+        &mut self.nodes[id.0]
+    }
+}
+
+// per-node facts computed after parsing, e.g. the (depth, slot) each VAR resolves to
+struct SideTable<T> {
+    entries: Vec<Option<T>>,
+}
+
+impl<T> SideTable<T> {
+    fn new() -> SideTable<T> {
+        SideTable {
+            entries: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, id: NodeId, value: T) {
+        if self.entries.len() <= id.0 {
+            self.entries.resize_with(id.0 + 1, || None);
+        }
+        self.entries[id.0] = Some(value);
+    }
+
+    fn get(&self, id: NodeId) -> Option<&T> {
+        self.entries.get(id.0).and_then(Option::as_ref)
+    }
+}
+
+// serialized as a map from node id to fact, leaving out the nodes without one
+impl<T: Serialize> Serialize for SideTable<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entries = self.entries.iter().enumerate();
+        serializer.collect_map(entries.filter_map(|(id, entry)| entry.as_ref().map(|entry| (NodeId(id), entry))))
     }
 }
 
+impl<T> std::ops::Index<NodeId> for SideTable<T> {
+    type Output = T;
+
+    fn index(&self, id: NodeId) -> &T {
+        self.get(id).expect("node has no side table entry")
+    }
+}
+
+#[derive(Serialize)]
 enum AST {
     BINOP(BinOp),
     NUM(Num),
@@ -203,99 +780,107 @@ enum AST {
     ASSIGN(Assign),
 }
 
+#[derive(Serialize)]
 struct BinOp {
-    left: Box<AST>,
-    token: Token,
+    left: NodeId,
     op: Token,
-    right: Box<AST>,
+    right: NodeId,
 }
 
 impl BinOp {
-    fn new(left: AST, op: Token, right: AST) -> BinOp {
+    fn new(left: NodeId, op: Token, right: NodeId) -> BinOp {
         BinOp {
-            left: Box::new(left),
-            token: op,
-            op: op,
-            right: Box::new(right),
+            left,
+            op,
+            right,
         }
     }
 }
 
+#[derive(Serialize)]
 struct Num {
     token: Token,
-    value: Option<Value>,
 }
 
 impl Num {
     fn new(token: Token) -> Num {
         Num {
-            token: token,
-            value: token.value,
+            token,
         }
     }
 }
 
+#[derive(Serialize)]
 struct UnaryOp {
-    token: Token,
     op: Token,
-    expr: Box<AST>,
+    expr: NodeId,
 }
 
 impl UnaryOp {
-    fn new(op: Token, expr: AST) -> UnaryOp {
+    fn new(op: Token, expr: NodeId) -> UnaryOp {
         UnaryOp {
-            token: op,
-            op: op,
-            expr: Box::new(expr)
+            op,
+            expr,
         }
     }
 }
 
+#[derive(Serialize)]
 struct Compound {
-    children : Vec<Box<AST>>,
+    children : Vec<NodeId>,
+    begin: Span,
+    end: Span,
 }
 
 impl Compound {
     fn new() -> Compound {
         Compound {
-            children: Vec::new()
+            children: Vec::new(),
+            begin: Span::default(),
+            end: Span::default(),
         }
     }
 }
 
+#[derive(Serialize)]
 struct Assign {
-    left: Box<AST>,
+    left: NodeId,
     op: Token,
-    token: Token,
-    right: Box<AST>,
+    right: NodeId,
 }
 
 impl Assign {
-    fn new(left: AST, op: Token, right: AST) -> Assign {
+    fn new(left: NodeId, op: Token, right: NodeId) -> Assign {
         Assign {
-            left: Box::new(left),
-            op: op,
-            token: op,
-            right: Box::new(right)
+            left,
+            op,
+            right,
         }
     }
 
 }
 
+#[derive(Serialize)]
 struct Var {
     token: Token,
-    value: Option<Value>,
 }
 
 impl Var {
     fn new(token: Token) -> Var{
         Var {
-            token: token,
-            value: token.value,
+            token,
+        }
+    }
+
+    fn name(&self) -> String {
+        match &self.token.value {
+            Some(Value::STRING(name)) => name.clone(),
+            _ => String::new(),
         }
     }
 }
 
+#[derive(Serialize)]
 struct NoOp {
 
 }
@@ -306,16 +891,25 @@ impl NoOp {
     }
 }
 
+trait ParseListener {
+    fn enter(&mut self, rule: &'static str, token: &Token);
+    fn exit(&mut self, rule: &'static str, token: &Token, ok: bool);
+    fn consume(&mut self, token: &Token);
+    fn mismatch(&mut self, expected: CalcTokenType, token: &Token);
+}
+
 struct Parser<'a> {
     lexer: Lexer<'a>,
     current_token: Option<Token>,
+    listener: Option<&'a mut dyn ParseListener>,
+    ast: ASTArena,
 }
 
 impl<'a> Display for Parser<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.current_token {
             Some(x) => write!(f, "lexer:{}, current_token:{}", &self.lexer, x),
-            None => write!(f, "lexer:{}, current_token: {}", &self.lexer, "None"),
+            None => write!(f, "lexer:{}, current_token: None", &self.lexer),
         }
     }
 }
@@ -324,154 +918,227 @@ impl<'a> Parser<'a> {
 
     fn new(lexer: Lexer<'a>) -> Parser<'a> {
         Parser {
-            lexer: lexer,
+            lexer,
+            current_token: None,
+            listener: None,
+            ast: ASTArena::new(),
+        }
+    }
+
+    fn with_listener(lexer: Lexer<'a>, listener: &'a mut dyn ParseListener) -> Parser<'a> {
+        Parser {
+            lexer,
             current_token: None,
+            listener: Some(listener),
+            ast: ASTArena::new(),
+        }
+    }
+
+    fn rule<T>(&mut self, rule: &'static str, body: impl FnOnce(&mut Parser<'a>) -> Result<T, char>) -> Result<T, char> {
+        if let (Some(listener), Some(token)) = (self.listener.as_mut(), self.current_token.as_ref()) {
+            listener.enter(rule, token);
         }
+        let result = body(self);
+        if let (Some(listener), Some(token)) = (self.listener.as_mut(), self.current_token.as_ref()) {
+            listener.exit(rule, token, result.is_ok());
+        }
+        result
     }
 
     fn eat(&mut self, token_type: CalcTokenType) -> Result<(), bool> {
         let current_token_type = self.current_token.as_ref().unwrap().genre;
         if current_token_type == token_type {
-            self.current_token = Some(self.lexer.get_next_token());
+            let token = self.current_token.replace(self.lexer.get_next_token());
+            if let (Some(listener), Some(token)) = (self.listener.as_mut(), token.as_ref()) {
+                listener.consume(token);
+            }
             Ok(())
         } else {
+            if let (Some(listener), Some(token)) = (self.listener.as_mut(), self.current_token.as_ref()) {
+                listener.mismatch(token_type, token);
+            }
             Err(true)
         }
     }
 
-    fn program(&self) -> Result<AST, char> {
-        let node = self.compound_statement();
-        let _ = self.eat(CalcTokenType::DOT);
-        return node;
+    fn program(&mut self) -> Result<NodeId, char> {
+        self.rule("program", |parser| {
+            let node = parser.compound_statement();
+            let _ = parser.eat(CalcTokenType::DOT);
+            node
+        })
     }
 
-    fn compound_statement(&self) -> Result<AST, char> {
-        let _ = self.eat(CalcTokenType::BEGIN);
-        let mut nodes = self.statement_list().unwrap();
-
-        let mut root = Compound::new();
-        root.children.append(&mut nodes);
-
-        return Ok(AST::COMPOUND(root));
+    fn compound_statement(&mut self) -> Result<NodeId, char> {
+        self.rule("compound_statement", |parser| {
+            let begin = parser.current_token.as_ref().unwrap().span;
+            let _ = parser.eat(CalcTokenType::BEGIN);
+            let mut nodes = parser.statement_list()?;
+            let end = parser.current_token.as_ref().unwrap().span;
+            let _ = parser.eat(CalcTokenType::END);
+
+            let mut root = Compound::new();
+            root.children.append(&mut nodes);
+            root.begin = begin;
+            root.end = end;
+
+            Ok(parser.ast.add(AST::COMPOUND(root)))
+        })
     }
 
-    fn statement_list(&self) -> Result<Vec<Box<AST>>, char> {
-        let statement = self.statement().unwrap();
-        let mut results = Vec::new();
-        results.push(Box::new(statement));
+    fn statement_list(&mut self) -> Result<Vec<NodeId>, char> {
+        self.rule("statement_list", |parser| {
+            let statement = parser.statement()?;
+            let mut results = Vec::new();
+            results.push(statement);
 
-        while self.current_token.unwrap().genre == CalcTokenType::SEMI {
-            let _ = self.eat(CalcTokenType::SEMI);
-            results.push(Box::new(self.statement().unwrap()));
-        }
+            while parser.current_token.as_ref().unwrap().genre == CalcTokenType::SEMI {
+                let _ = parser.eat(CalcTokenType::SEMI);
+                results.push(parser.statement()?);
+            }
 
-        if self.current_token.unwrap().genre == CalcTokenType::ID {
-            return Err('G');
-        }
+            if parser.current_token.as_ref().unwrap().genre == CalcTokenType::ID {
+                return Err('G');
+            }
 
-        return Ok(results);
+            Ok(results)
+        })
     }
 
-    fn statement(&self) -> Result<AST, char> {
-        let token_type =self.current_token.unwrap().genre;
-        if token_type == CalcTokenType::BEGIN {
-            self.compound_statement()
-        } else if token_type == CalcTokenType::ID {
-            self.assignment_statement()
-        } else {
-            self.empty()
-        }
+    fn statement(&mut self) -> Result<NodeId, char> {
+        self.rule("statement", |parser| {
+            let token_type = parser.current_token.as_ref().unwrap().genre;
+            if token_type == CalcTokenType::BEGIN {
+                parser.compound_statement()
+            } else if token_type == CalcTokenType::ID {
+                parser.assignment_statement()
+            } else {
+                parser.empty()
+            }
+        })
     }
 
-    fn assignment_statement(&self) -> Result<AST, char> {
-        let left = self.variable().unwrap();
-        let token = self.current_token.unwrap();
-        let _ = self.eat(CalcTokenType::ASSIGN);
-        let right = self.expr().unwrap();
-        let node = Assign::new(left, token, right);
-        return Ok(AST::ASSIGN(node));
+    fn assignment_statement(&mut self) -> Result<NodeId, char> {
+        self.rule("assignment_statement", |parser| {
+            let left = parser.variable()?;
+            let token = parser.current_token.clone().unwrap();
+            let _ = parser.eat(CalcTokenType::ASSIGN);
+            let right = parser.expr()?;
+            let node = Assign::new(left, token, right);
+            Ok(parser.ast.add(AST::ASSIGN(node)))
+        })
     }
 
-    fn variable(&self) -> Result<AST, char> {
-        let node = Var::new(self.current_token.unwrap());
-        let _ = self.eat(CalcTokenType::ID);
-        return Ok(AST::VAR(node));
+    fn variable(&mut self) -> Result<NodeId, char> {
+        self.rule("variable", |parser| {
+            let node = Var::new(parser.current_token.clone().unwrap());
+            let _ = parser.eat(CalcTokenType::ID);
+            Ok(parser.ast.add(AST::VAR(node)))
+        })
     }
 
-    fn empty(&self) -> Result<AST, char> {
-        Ok(AST::NOOP(NoOp::new()))
+    fn empty(&mut self) -> Result<NodeId, char> {
+        self.rule("empty", |parser| Ok(parser.ast.add(AST::NOOP(NoOp::new()))))
     }
 
-    fn factor(&mut self) -> Result<AST, char> {
-        let token = self.current_token;
-        let token_type = &token.unwrap().genre;
-        let _value = &token.unwrap().value.unwrap();
+    fn factor(&mut self) -> Result<NodeId, char> {
+        self.rule("factor", |parser| {
+            let token = parser.current_token.clone();
+            let token_type = &token.as_ref().unwrap().genre;
+
+            if token_type == &CalcTokenType::INTEGER {
+                let _ = parser.eat(CalcTokenType::INTEGER);
+
+                Ok(parser.ast.add(AST::NUM(Num::new(token.unwrap()))))
+            } else if token_type == &CalcTokenType::LPAREN {
+                let _ = parser.eat(CalcTokenType::LPAREN);
+                let node = parser.expr()?;
+                parser.eat(CalcTokenType::RPAREN).map_err(|_| ')')?;
+
+                Ok(node)
+            } else if token_type == &CalcTokenType::PLUS {
+                let _ = parser.eat(CalcTokenType::PLUS);
+                let expr = parser.factor()?;
+                let node = parser.ast.add(AST::UNARYOP(UnaryOp::new(token.unwrap(), expr)));
+
+                Ok(node)
+            } else if token_type == &CalcTokenType::MINUS {
+                let _ = parser.eat(CalcTokenType::MINUS);
+                let expr = parser.factor()?;
+                let node = parser.ast.add(AST::UNARYOP(UnaryOp::new(token.unwrap(), expr)));
+
+                Ok(node)
+            } else {
+                parser.variable()
+            }
+        })
+    }
 
-        if token_type == &CalcTokenType::INTEGER {
-            let _ = self.eat(CalcTokenType::INTEGER);
+    fn term(&mut self) -> Result<NodeId, char> {
+        self.rule("term", |parser| {
+            let mut node = parser.factor()?;
+            let action = [CalcTokenType::MUL, CalcTokenType::DIV];
+            while action.contains(&parser.current_token.as_ref().unwrap().genre) {
+                let token = parser.current_token.clone().unwrap();
+                if token.genre == CalcTokenType::MUL {
+                    let _= parser.eat(CalcTokenType::MUL);
+                } else if token.genre == CalcTokenType::DIV {
+                    let _= parser.eat(CalcTokenType::DIV);
+                }
+
+                let right = parser.factor()?;
+                node = parser.ast.add(AST::BINOP(BinOp::new(node, token, right)));
+            }
 
-            Ok(AST::NUM(Num::new(token.unwrap())))
-        } else if token_type == &CalcTokenType::LPAREN {
-            let _ = self.eat(CalcTokenType::LPAREN);
-            let node = self.expr();
-            let _ = self.eat(CalcTokenType::RPAREN);
+            Ok(node)
+        })
+    }
 
-            node
-        } else if token_type == &CalcTokenType::PLUS {
-            let _ = self.eat(CalcTokenType::PLUS);
-            let node = AST::UNARYOP(UnaryOp::new(token.unwrap(), self.factor()?));
+    fn expr(&mut self) -> Result<NodeId, char> {
+        self.rule("expr", |parser| {
+            let mut node = parser.term()?;
+            let action = [CalcTokenType::PLUS, CalcTokenType::MINUS];
+            while action.contains(&parser.current_token.as_ref().unwrap().genre) {
+                let token = parser.current_token.clone().unwrap();
+                if token.genre == CalcTokenType::PLUS{
+                    let _= parser.eat(CalcTokenType::PLUS);
+                } else if token.genre == CalcTokenType::MINUS {
+                    let _= parser.eat(CalcTokenType::MINUS);
+                }
+
+                let right = parser.term()?;
+                node = parser.ast.add(AST::BINOP(BinOp::new(node, token, right)));
+            }
 
             Ok(node)
-        } else if token_type == &CalcTokenType::MINUS {
-            let _ = self.eat(CalcTokenType::MINUS);
-            let node = AST::UNARYOP(UnaryOp::new(token.unwrap(), self.factor()?));
+        })
+    }
 
-            Ok(node)
+    fn parse_expr(&mut self) -> Result<NodeId, char> {
+        self.current_token = Some(self.lexer.get_next_token());
+        let node = self.expr();
+        if !self.at_end() {
+            Err('Z')
         } else {
-            self.variable()
+            node
         }
     }
 
-    fn term(&mut self) -> Result<AST, char> {
-
-        let mut node = self.factor()?;
-        let action = vec![CalcTokenType::MUL, CalcTokenType::DIV];
-        while action.contains(&self.current_token.unwrap().genre) {
-            let token = self.current_token.unwrap();
-            if token.genre == CalcTokenType::MUL {
-                let _= self.eat(CalcTokenType::MUL);
-            } else if token.genre == CalcTokenType::DIV {
-                let _= self.eat(CalcTokenType::DIV);
-            }
-
-            node = AST::BINOP(BinOp::new(node, token, self.factor()?));
-        }
-
-        return Ok(node);
+    // an EOF token holding a character is where the lexer met one it does not know
+    fn at_end(&self) -> bool {
+        let token = self.current_token.as_ref().unwrap();
+        token.genre == CalcTokenType::EOF && token.value.is_none()
     }
 
-    fn expr(&mut self) -> Result<AST, char> {
-
-        let mut node = self.term()?;
-        let action = vec![CalcTokenType::PLUS, CalcTokenType::MINUS];
-        while action.contains(&self.current_token.unwrap().genre) {
-            let token = self.current_token.unwrap();
-            if token.genre == CalcTokenType::PLUS{
-                let _= self.eat(CalcTokenType::PLUS);
-            } else if token.genre == CalcTokenType::MINUS {
-                let _= self.eat(CalcTokenType::MINUS);
-            }
-
-            node = AST::BINOP(BinOp::new(node, token, self.term()?));
-        }
-
-        return Ok(node);
+    // the token the parser stopped at, which is where a syntax error is reported
+    fn span(&self) -> Span {
+        self.current_token.as_ref().map(|token| token.span).unwrap_or_default()
     }
 
-    fn parse(&mut self) -> Result<AST, char> {
+    fn parse(&mut self) -> Result<NodeId, char> {
         self.current_token = Some(self.lexer.get_next_token());
         let node = self.program();
-        if self.current_token.unwrap().genre != CalcTokenType::EOF {
+        if !self.at_end() {
             Err('Z')
         } else {
             node
@@ -479,150 +1146,1574 @@ impl<'a> Parser<'a> {
     }
 }
 
-struct Interpreter<'a> {
-    parser: Parser<'a>,
-    temp: HashMap<Value, i32>,
+#[derive(Clone, Debug)]
+#[derive(Serialize)]
+struct VarSymbol {
+    name: String,
+    slot: usize,
 }
 
-impl<'a> Interpreter<'a> {
-    fn new(parser: Parser<'a>) -> Interpreter {
-        Interpreter {
-            parser: parser,
-            temp: HashMap::new(),
-        }
-    }
+#[derive(Serialize)]
+struct ScopedSymbolTable {
+    #[serde(serialize_with = "serialize_symbols")]
+    symbols: HashMap<String, VarSymbol>,
+    scope_name: String,
+    scope_level: usize,
+    enclosing_scope: Option<Box<ScopedSymbolTable>>,
+}
 
-    fn visit(&self, node: Box<AST>) -> i32 {
-        match *node {
-            AST::BINOP(bin_op) => {
-                self.visit_binop(bin_op)
-            },
-            AST::NUM(num) => {
-                self.visit_num(num)
-            },
-            AST::UNARYOP(unary_op) => {
-                self.visit_unaryop(unary_op)
-            },
-            AST::COMPOUND(compound) => {
-                let _= self.visit_compound(compound);
-                0
-            },
-            AST::ASSIGN(assign) => {
-                let _= self.visit_assign(assign);
-                0
-            },
-            AST::VAR(var) => {
-                let _= self.visit_var(var);
-                0
-            },
-            AST::NOOP(no_op) => {
-                let _= self.visit_noop(no_op);
-                0
-            },
+// symbols are listed in slot order so the output is stable
+fn serialize_symbols<S: serde::Serializer>(symbols: &HashMap<String, VarSymbol>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut symbols: Vec<&VarSymbol> = symbols.values().collect();
+    symbols.sort_by_key(|symbol| symbol.slot);
+    serializer.collect_seq(symbols)
+}
+
+impl ScopedSymbolTable {
+    fn new(scope_name: &str, scope_level: usize, enclosing_scope: Option<Box<ScopedSymbolTable>>) -> ScopedSymbolTable {
+        ScopedSymbolTable {
+            symbols: HashMap::new(),
+            scope_name: scope_name.to_string(),
+            scope_level,
+            enclosing_scope,
         }
     }
 
-    fn visit_binop(&self, node: BinOp) -> i32{
-        let op_type = node.op.genre;
-
-        match op_type {
-            CalcTokenType::PLUS => self.visit(node.left) + self.visit(node.right),
-            CalcTokenType::MINUS => self.visit(node.left) - self.visit(node.right),
-            CalcTokenType::MUL => self.visit(node.left) * self.visit(node.right),
-            CalcTokenType::DIV => self.visit(node.left) / self.visit(node.right),
-            _ => {
-                println!("Failed to found error op type: {}", op_type);
-                0
-            },
-        }
+    fn insert(&mut self, name: &str) -> &VarSymbol {
+        let slot = self.symbols.len();
+        self.symbols.entry(name.to_string()).or_insert(VarSymbol { name: name.to_string(), slot })
     }
 
-    fn visit_num(&self, node: Num) -> i32{
-        match node.value.unwrap() {
-            Value::CHAR(x) => {
-                println!("Failed to get value: {}, it should be numeric.", x);
-                0
-            },
-            Value::INT(y) => y,
-            Value::STRING(z) => {
-                println!("Failed to get num value: {}", z);
-                0
+    fn lookup(&self, name: &str, current_scope_only: bool) -> Option<(usize, &VarSymbol)> {
+        let mut scope = Some(self);
+        let mut depth = 0;
+        while let Some(table) = scope {
+            if let Some(symbol) = table.symbols.get(name) {
+                return Some((depth, symbol));
+            }
+            if current_scope_only {
+                break;
             }
+            scope = table.enclosing_scope.as_deref();
+            depth += 1;
         }
+        None
     }
 
-    fn visit_unaryop(&self, node: UnaryOp) -> i32 {
-        let op = node.op.genre;
-        match op {
-            CalcTokenType::PLUS => {
-                self.visit(node.expr)
-            },
-            CalcTokenType::MINUS => {
-                -self.visit(node.expr)
-            },
-            _ => {
-                println!("Failed to parse error op type: {}", op);
-                0
-            },
+    fn enclosing(&self, depth: usize) -> &ScopedSymbolTable {
+        let mut scope = self;
+        for _ in 0..depth {
+            scope = scope.enclosing_scope.as_deref().unwrap();
+        }
+        scope
+    }
+
+    fn slot_names(&self) -> Vec<String> {
+        let mut names = vec![String::new(); self.symbols.len()];
+        for symbol in self.symbols.values() {
+            names[symbol.slot] = symbol.name.clone();
+        }
+        names
+    }
+}
+
+struct SemanticAnalyzer {
+    current_scope: ScopedSymbolTable,
+    slots: SideTable<(usize, usize)>,
+}
+
+impl SemanticAnalyzer {
+    fn new() -> SemanticAnalyzer {
+        SemanticAnalyzer {
+            current_scope: ScopedSymbolTable::new("global", 1, None),
+            slots: SideTable::new(),
+        }
+    }
+
+    fn analyze(mut self, ast: &ASTArena, root: NodeId) -> (ScopedSymbolTable, SideTable<(usize, usize)>) {
+        self.visit(ast, root);
+        (self.current_scope, self.slots)
+    }
+}
+
+impl Visitor for SemanticAnalyzer {
+    type Result = ();
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) {
+        self.visit(ast, node.right);
+        self.visit(ast, node.left);
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, id: NodeId, node: &Var) {
+        let name = node.name();
+        let (depth, slot) = match self.current_scope.lookup(&name, false) {
+            Some((depth, symbol)) => (depth, symbol.slot),
+            None => (0, self.current_scope.insert(&name).slot),
+        };
+        self.slots.insert(id, (depth, slot));
+    }
+}
+
+#[derive(Serialize)]
+struct Diagnostic {
+    span: Span,
+    message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Warning at {}: {}", self.span, self.message)
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum ARType {
+    PROGRAM,
+}
+
+impl Display for ARType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ARType::PROGRAM => write!(f, "PROGRAM"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ActivationRecord {
+    name: String,
+    genre: ARType,
+    nesting_level: usize,
+    call_line: Option<usize>,
+    static_link: Option<usize>,
+    names: Vec<String>,
+    slots: Vec<Option<Value>>,
+}
+
+impl ActivationRecord {
+    fn new(name: &str, genre: ARType, nesting_level: usize, call_line: Option<usize>, names: Vec<String>) -> ActivationRecord {
+        ActivationRecord {
+            name: name.to_string(),
+            genre,
+            nesting_level,
+            call_line,
+            static_link: None,
+            slots: vec![None; names.len()],
+            names,
+        }
+    }
+
+    fn get(&self, slot: usize) -> Option<&Value> {
+        self.slots[slot].as_ref()
+    }
+
+    fn set(&mut self, slot: usize, value: Value) -> Option<Value> {
+        self.slots[slot].replace(value)
+    }
+
+    fn members(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.names.iter().zip(&self.slots).filter_map(|(name, slot)| slot.as_ref().map(|value| (name, value)))
+    }
+}
+
+impl Display for ActivationRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} {}", self.nesting_level, self.genre, self.name)?;
+        if let Some(line) = self.call_line {
+            write!(f, " (called from line {})", line)?;
+        }
+
+        for (name, value) in self.members() {
+            write!(f, "\n   {:<20}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+struct CallStack {
+    records: Vec<ActivationRecord>,
+}
+
+impl CallStack {
+    fn new() -> CallStack {
+        CallStack {
+            records: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, record: ActivationRecord) {
+        self.records.push(record);
+    }
+
+    fn peek(&mut self) -> &mut ActivationRecord {
+        self.records.last_mut().unwrap()
+    }
+
+    fn frame(&mut self, depth: usize) -> &mut ActivationRecord {
+        let mut index = self.records.len() - 1;
+        for _ in 0..depth {
+            index = self.records[index].static_link.unwrap();
+        }
+        &mut self.records[index]
+    }
+}
+
+struct Runtime {
+    call_stack: CallStack,
+    arithmetic: Arithmetic,
+    limits: Limits,
+    steps: u64,
+    allocated: usize,
+    started: Instant,
+    // where --trace writes each statement as it runs
+    trace: Option<Box<dyn Write>>,
+}
+
+impl Runtime {
+    fn new() -> Runtime {
+        Runtime {
+            call_stack: CallStack::new(),
+            arithmetic: Arithmetic::new(),
+            limits: Limits::default(),
+            steps: 0,
+            allocated: 0,
+            started: Instant::now(),
+            trace: None,
+        }
+    }
+
+    fn start(&mut self, names: &[String], overflow: Option<OverflowMode>, inputs: &[(String, Value)]) -> Result<(), InterpretError> {
+        if let Some(mode) = overflow {
+            self.arithmetic.overflow = mode;
+        }
+        self.started = Instant::now();
+        self.push_frame(ActivationRecord::new("main", ARType::PROGRAM, 1, None, names.to_vec()))
+            .map_err(|code| InterpretError::RUNTIME(RuntimeError::unspanned(code)))?;
+
+        for (name, value) in inputs {
+            let result = match names.iter().position(|n| n == name) {
+                Some(slot) => self.arithmetic.literal(value.clone()).and_then(|value| self.assign(0, slot, value)),
+                None => Err(ErrorCode::UNDEFINEDVARIABLE(name.clone())),
+            };
+            result.map_err(|code| self.fail(RuntimeError::unspanned(code)))?;
+        }
+        Ok(())
+    }
+
+    fn fail(&self, mut error: RuntimeError) -> InterpretError {
+        error.stack = self.call_stack.records.clone();
+        InterpretError::RUNTIME(error)
+    }
+
+    fn step(&mut self) -> Result<(), ErrorCode> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(ErrorCode::LIMITEXCEEDED(Limit::STEPS(max)));
+            }
+        }
+        if let Some(timeout) = self.limits.timeout {
+            if self.started.elapsed() > timeout {
+                return Err(ErrorCode::LIMITEXCEEDED(Limit::TIMEOUT(timeout)));
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, step: &TraceStep) {
+        if let Some(trace) = &mut self.trace {
+            writeln!(trace, "{}", step).and_then(|_| trace.flush()).ok();
+        }
+    }
+
+    fn push_frame(&mut self, record: ActivationRecord) -> Result<(), ErrorCode> {
+        if let Some(max) = self.limits.max_depth {
+            if self.call_stack.records.len() >= max {
+                return Err(ErrorCode::LIMITEXCEEDED(Limit::DEPTH(max)));
+            }
+        }
+        self.call_stack.push(record);
+        Ok(())
+    }
+
+    fn track_allocation(&mut self, added: usize, freed: usize) -> Result<(), ErrorCode> {
+        self.allocated = (self.allocated + added).saturating_sub(freed);
+        if let Some(max) = self.limits.max_memory {
+            if self.allocated > max {
+                return Err(ErrorCode::LIMITEXCEEDED(Limit::MEMORY(max)));
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup(&mut self, depth: usize, slot: usize) -> Result<Value, ErrorCode> {
+        let frame = self.call_stack.frame(depth);
+        match frame.get(slot) {
+            Some(val) => Ok(val.clone()),
+            None => Err(ErrorCode::UNDEFINEDVARIABLE(frame.names[slot].clone())),
+        }
+    }
+
+    // a member defined for the first time also brings its name into the frame
+    fn assign(&mut self, depth: usize, slot: usize, value: Value) -> Result<(), ErrorCode> {
+        let frame = self.call_stack.frame(depth);
+        let (added, freed) = match frame.get(slot) {
+            Some(old) => (value.size(), old.size()),
+            None => (value.size() + frame.names[slot].len(), 0),
+        };
+        // checked before the store, so the dump shows the frame as it was
+        self.track_allocation(added, freed)?;
+        self.call_stack.frame(depth).set(slot, value);
+        Ok(())
+    }
+}
+
+struct Interpreter<'a> {
+    runtime: &'a mut Runtime,
+    slots: &'a SideTable<(usize, usize)>,
+    reads: Vec<(String, Value)>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(runtime: &'a mut Runtime, slots: &'a SideTable<(usize, usize)>) -> Interpreter<'a> {
+        Interpreter {
+            runtime,
+            slots,
+            reads: Vec::new(),
         }
     }
+}
+
+impl VisitResult for Result<Value, RuntimeError> {
+    fn output() -> Self {
+        Ok(Value::UNIT)
+    }
+
+    fn branch(&self) -> bool {
+        self.is_err()
+    }
+}
+
+impl<'a> Visitor for Interpreter<'a> {
+    type Result = Result<Value, RuntimeError>;
 
-    fn visit_compound(self, node: Compound) {
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) -> Result<Value, RuntimeError> {
+        let op_type = node.op.genre;
+        let left = self.visit(ast, node.left)?;
+        let right = self.visit(ast, node.right)?;
+
+        left.arith(op_type, &right, self.runtime.arithmetic)
+            .map_err(|code| RuntimeError::new(code, node.op.span))
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) -> Result<Value, RuntimeError> {
+        let value = node.token.value.clone().unwrap_or(Value::UNIT);
+        self.runtime.arithmetic.literal(value).map_err(|code| RuntimeError::new(code, node.token.span))
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) -> Result<Value, RuntimeError> {
+        let op = node.op.genre;
+        let operand = self.visit(ast, node.expr)?;
+        let result = match op {
+            CalcTokenType::PLUS => operand.pos(),
+            CalcTokenType::MINUS => operand.neg(self.runtime.arithmetic),
+            _ => Err(ErrorCode::INVALIDOPERATOR(op)),
+        };
+        result.map_err(|code| RuntimeError::new(code, node.op.span))
+    }
+
+    fn visit_compound(&mut self, ast: &ASTArena, _id: NodeId, node: &Compound) -> Result<Value, RuntimeError> {
+        self.runtime.step().map_err(RuntimeError::unspanned)?;
         for child in &node.children {
-            let _= self.visit(*child);
+            self.visit(ast, *child)?;
+        }
+        Ok(Value::UNIT)
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, id: NodeId, node: &Assign) -> Result<Value, RuntimeError> {
+        self.runtime.step().map_err(|code| RuntimeError::new(code, node.op.span))?;
+        if let AST::VAR(var) = &ast[node.left] {
+            self.reads.clear();
+            let (depth, slot) = self.slots[node.left];
+            let result = self.visit(ast, node.right).and_then(|value| {
+                self.runtime.assign(depth, slot, value.clone()).map_err(|code| RuntimeError::new(code, node.op.span))?;
+                Ok(value)
+            });
+            // a failing statement is traced too, with whatever it read before the error
+            if self.runtime.trace.is_some() {
+                let step = TraceStep::new(
+                    node.op.span.line,
+                    format::statement(ast, id),
+                    std::mem::take(&mut self.reads),
+                    result.as_ref().ok().map(|value| (var.name(), value.clone())),
+                );
+                self.runtime.record(&step);
+            }
+            result?;
         }
+        Ok(Value::UNIT)
     }
 
-    fn visit_assign(self, node: Assign) {
-        let name = node.left;
-        match *name {
-            AST::VAR(var) => {
-                self.temp.insert(var.value.unwrap(), self.visit(node.right));
+    fn visit_var(&mut self, _ast: &ASTArena, id: NodeId, node: &Var) -> Result<Value, RuntimeError> {
+        let (depth, slot) = self.slots[id];
+        let value = self.runtime.lookup(depth, slot).map_err(|code| RuntimeError::new(code, node.token.span))?;
+        if self.runtime.trace.is_some() && !self.reads.iter().any(|(name, _)| *name == node.name()) {
+            self.reads.push((node.name(), value.clone()));
+        }
+        Ok(value)
+    }
+
+    fn visit_noop(&mut self, _ast: &ASTArena, _id: NodeId, _node: &NoOp) -> Result<Value, RuntimeError> {
+        self.runtime.step().map_err(RuntimeError::unspanned)?;
+        Ok(Value::UNIT)
+    }
+}
+
+struct Program {
+    ast: ASTArena,
+    root: NodeId,
+    slots: SideTable<(usize, usize)>,
+    globals: Vec<String>,
+    overflow: Option<OverflowMode>,
+}
+
+impl Program {
+    fn parse(text: &String) -> Result<Program, InterpretError> {
+        Program::from_parser(Parser::new(Lexer::new(text)))
+    }
+
+    fn from_parser(mut parser: Parser) -> Result<Program, InterpretError> {
+        let root = parser.parse().map_err(|code| InterpretError::SYNTAX(code, parser.span()))?;
+        let (scope, slots) = SemanticAnalyzer::new().analyze(&parser.ast, root);
+        Ok(Program {
+            ast: parser.ast,
+            root,
+            slots,
+            globals: scope.slot_names(),
+            overflow: parser.lexer.overflow,
+        })
+    }
+
+    // a {$Q±} directive in the source overrides the overflow mode given on the command line
+    fn arithmetic(&self, mut arithmetic: Arithmetic) -> Arithmetic {
+        if let Some(mode) = self.overflow {
+            arithmetic.overflow = mode;
+        }
+        arithmetic
+    }
+
+    fn fold(&mut self, arithmetic: Arithmetic) -> Vec<Diagnostic> {
+        let arithmetic = self.arithmetic(arithmetic);
+        let (root, diagnostics) = fold::fold(&mut self.ast, self.root, arithmetic);
+        self.root = root;
+        diagnostics
+    }
+
+    fn run(&self, runtime: &mut Runtime, inputs: &[(String, Value)]) -> Result<Value, InterpretError> {
+        runtime.start(&self.globals, self.overflow, inputs)?;
+        let result = Interpreter::new(runtime, &self.slots).visit(&self.ast, self.root);
+        result.map_err(|e| runtime.fail(e))
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum Backend {
+    AST,
+    VM,
+}
+
+struct Options {
+    backend: Backend,
+    arithmetic: Arithmetic,
+    limits: Limits,
+    output: Option<String>,
+    inputs: Vec<Vec<(String, Value)>>,
+    optimize: bool,
+    passes: Vec<ir::Pass>,
+    dump_ir: bool,
+    dot: bool,
+    check: bool,
+    trace: bool,
+    trace_parser: bool,
+}
+
+impl Options {
+    fn new() -> Options {
+        Options {
+            backend: Backend::AST,
+            arithmetic: Arithmetic::new(),
+            limits: Limits::default(),
+            output: None,
+            inputs: Vec::new(),
+            optimize: false,
+            passes: ir::Pass::all(),
+            dump_ir: false,
+            dot: false,
+            check: false,
+            trace: false,
+            trace_parser: false,
+        }
+    }
+
+    fn runtime(&self) -> Runtime {
+        let mut runtime = Runtime::new();
+        runtime.arithmetic = self.arithmetic;
+        runtime.limits = self.limits;
+        if self.trace {
+            runtime.trace = Some(Box::new(io::stdout()));
+        }
+        runtime
+    }
+}
+
+enum Executable {
+    TREE(Program),
+    CHUNK(bytecode::Chunk),
+}
+
+impl Executable {
+    fn load(text: &String, options: &Options) -> Result<Executable, InterpretError> {
+        let program = prepare(text, options)?;
+        match options.backend {
+            Backend::AST => {
+                if options.dump_ir {
+                    optimize(&program, options);
+                }
+                Ok(Executable::TREE(program))
             },
-            _ => {},
+            Backend::VM => Ok(Executable::CHUNK(compile(&program, options))),
+        }
+    }
+
+    fn run(&self, runtime: &mut Runtime, inputs: &[(String, Value)]) -> Result<Value, InterpretError> {
+        match self {
+            Executable::TREE(program) => program.run(runtime, inputs),
+            Executable::CHUNK(chunk) => bytecode::VM::new(chunk, runtime).run(inputs),
         }
     }
+}
 
-    fn visit_var(self, node: Var) -> i32 {
-        let var_name = node.value;
-        let val = self.temp.get(&var_name.unwrap());
-        *val.unwrap()
+fn optimize(program: &Program, options: &Options) -> ir::Function {
+    let mut function = ir::lower(program);
+    if options.dump_ir {
+        println!("; IR before optimization\n{}", function);
+    }
+    function.optimize(&options.passes, options.arithmetic);
+    if options.dump_ir {
+        println!("; IR after optimization\n{}", function);
     }
+    function
+}
 
-    fn visit_noop(self, node: NoOp) -> i32 {
-        0
+fn prepare(text: &String, options: &Options) -> Result<Program, InterpretError> {
+    let mut program = if options.trace_parser {
+        let mut tracer = parse_trace::ParseTracer::new(Box::new(io::stderr()));
+        Program::from_parser(Parser::with_listener(Lexer::new(text), &mut tracer))?
+    } else {
+        Program::parse(text)?
+    };
+    // the trace shows each statement as written, not with its constants already folded
+    if options.passes.contains(&ir::Pass::FOLD) && !options.trace {
+        for diagnostic in program.fold(options.arithmetic) {
+            eprintln!("{}", diagnostic);
+        }
     }
+    Ok(program)
+}
 
-    fn interpret(&mut self) -> Result<i32, char> {
-        let tree = self.parser.parse()?;
-        return Ok(self.visit(Box::new(tree)));
+fn compile(program: &Program, options: &Options) -> bytecode::Chunk {
+    let arithmetic = program.arithmetic(options.arithmetic);
+    if options.optimize {
+        bytecode::compile_function(&optimize(program, options), arithmetic)
+    } else {
+        if options.dump_ir {
+            optimize(program, options);
+        }
+        bytecode::compile(program, arithmetic)
     }
 }
 
-fn main() -> io::Result<()>{
+// inputs are integers, like the only literals a program can write
+fn parse_input(text: &str) -> Option<Value> {
+    if let Ok(n) = text.parse::<i64>() {
+        Some(Value::INT(n))
+    } else {
+        text.parse::<BigInt>().ok().map(Value::BIGINT)
+    }
+}
+
+fn parse_inputs(text: &str) -> Option<Vec<(String, Value)>> {
+    let mut inputs = Vec::new();
+    for pair in text.split(',').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=')?;
+        inputs.push((name.trim().to_lowercase(), parse_input(value.trim())?));
+    }
+    Some(inputs)
+}
+
+fn report(result: Result<Value, InterpretError>, runtime: &mut Runtime) -> String {
+    let mut output = String::new();
+    match result {
+        Ok(v) => {
+            for (k, v) in runtime.call_stack.peek().members() {
+                output += &format!("{}: {}\n", k, v);
+            }
+            if v != Value::UNIT {
+                output += &format!("{}\n", v);
+            }
+        },
+        Err(e) => output += &format!("Error when calculate expression: {}\n", e),
+    }
+    output
+}
+
+// the lines `run` starts a runtime error in the main program with; the compiled backends print them
+// before dumping the frame themselves
+fn failure(code: ErrorCode, span: Span) -> String {
+    let mut error = RuntimeError::new(code, span);
+    error.stack.push(ActivationRecord::new("main", ARType::PROGRAM, 1, None, Vec::new()));
+    format!("Error when calculate expression: {}", InterpretError::RUNTIME(error))
+}
+
+fn execute(executable: &Executable, options: &Options) {
+    if options.inputs.is_empty() {
+        let mut runtime = options.runtime();
+        let result = executable.run(&mut runtime, &[]);
+        print!("{}", report(result, &mut runtime));
+        return;
+    }
+
+    // a trace is written while the program runs, so traced runs go one after another
+    if options.trace {
+        for inputs in &options.inputs {
+            print_inputs(inputs);
+            let mut runtime = options.runtime();
+            let result = executable.run(&mut runtime, inputs);
+            print!("{}", report(result, &mut runtime));
+        }
+        return;
+    }
+
+    let outputs: Vec<String> = std::thread::scope(|scope| {
+        let handles: Vec<_> = options.inputs.iter().map(|inputs| {
+            scope.spawn(move || {
+                let mut runtime = options.runtime();
+                let result = executable.run(&mut runtime, inputs);
+                report(result, &mut runtime)
+            })
+        }).collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+    for (inputs, output) in options.inputs.iter().zip(outputs) {
+        print_inputs(inputs);
+        print!("{}", output);
+    }
+}
+
+fn print_inputs(inputs: &[(String, Value)]) {
+    let inputs: Vec<String> = inputs.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    println!("== run ({})", inputs.join(", "));
+}
+
+fn repl(options: &Options) -> io::Result<()> {
     loop {
         print!("calc > ");
         io::stdout().flush().unwrap();
 
         let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Ok(());
+        }
 
         let user_input = input.trim().to_string();
-        let lexer = Lexer::new(&user_input);
-        let parser = Parser::new(lexer);
-        let mut interpreter = Interpreter::new(parser);
-        let result = interpreter.interpret();
-        let temp = interpreter.temp;
+        if let Some(text) = user_input.strip_prefix(":eval-rpn ") {
+            match notation::eval_rpn(text, options.arithmetic) {
+                Ok(value) => println!("{}", value),
+                Err(e) => println!("Error when evaluate RPN: {}", e),
+            }
+        } else if let Some(text) = user_input.strip_prefix(":explain ") {
+            let text = text.to_string();
+            let mut parser = Parser::new(Lexer::new(&text));
+            match parser.parse_expr() {
+                Ok(id) => {
+                    let mut steps = Vec::new();
+                    let result = explain::explain(&mut parser.ast, id, options.arithmetic, &mut steps);
+                    println!("{}", steps.join(" => "));
+                    if let Err(e) = result {
+                        println!("Error when explain expression: {}", e);
+                    }
+                },
+                Err(e) => println!("Error when explain expression: {}", InterpretError::SYNTAX(e, parser.span())),
+            }
+        } else if let Some((command, text)) = user_input.split_once(' ').filter(|(c, _)| *c == ":rpn" || *c == ":lisp") {
+            let translate = if command == ":rpn" { notation::rpn } else { notation::lisp };
+            let text = text.to_string();
+            let mut parser = Parser::new(Lexer::new(&text));
+            match parser.parse_expr() {
+                Ok(id) => println!("{}", translate(&parser.ast, id)),
+                Err(e) => println!("Error when translate expression: {}", InterpretError::SYNTAX(e, parser.span())),
+            }
+        } else {
+            match Executable::load(&user_input, options) {
+                Ok(executable) => execute(&executable, options),
+                Err(e) => println!("Error when calculate expression: {}", e),
+            }
+        }
+        io::stdout().flush().unwrap();
+    }
+}
 
-        for (k, v) in &temp {
-            println!("{}: {}", k, v);
+fn run_file(path: &str, options: &Options) -> io::Result<()> {
+    let bytes = std::fs::read(path)?;
+    let executable = if bytecode::Chunk::is_compiled(&bytes) {
+        Executable::CHUNK(bytecode::Chunk::from_bytes(&bytes)?)
+    } else {
+        let text = String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        match Executable::load(&text, options) {
+            Ok(executable) => executable,
+            Err(e) => {
+                println!("Error when calculate expression: {}", e);
+                return Ok(());
+            },
         }
+    };
+    execute(&executable, options);
+    Ok(())
+}
 
-        match result {
-            Ok(v) => println!("{v:?}"),
-            Err(e) => println!("Error when calculate expression: {e:?}"),
+fn compile_file(path: &str, options: &Options) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    match prepare(&text, options) {
+        Ok(program) => {
+            let chunk = compile(&program, options);
+            let output = match &options.output {
+                Some(output) => output.clone(),
+                None => std::path::Path::new(path).with_extension("lbc").to_string_lossy().into_owned(),
+            };
+            std::fs::write(output, chunk.to_bytes()?)
+        },
+        Err(e) => {
+            println!("Error when compile program: {}", e);
+            Ok(())
+        },
+    }
+}
+
+fn ast_file(path: &str, options: &Options) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    match Program::parse(&text) {
+        Ok(program) if options.dot => print!("{}", visualize::gendot(&program.ast, program.root)),
+        Ok(program) => print!("{}", visualize::outline(&program.ast, program.root)),
+        Err(e) => println!("Error when parse program: {}", e),
+    }
+    Ok(())
+}
+
+fn parse_tree_file(path: &str, options: &Options) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    match parse_tree::parse(&text) {
+        Err(e) => println!("Error when parse program: {}", e),
+        Ok(Some(root)) if options.dot => print!("{}", root.gendot()),
+        Ok(Some(root)) => print!("{}", root.outline(0)),
+        Ok(None) => {},
+    }
+    Ok(())
+}
+
+// the formatted text, or the message `fmt` fails with; under --check the text is only compared
+fn fmt_text(path: &str, text: &String, options: &Options) -> Result<String, String> {
+    let formatted = match Program::parse(text) {
+        Ok(program) => format::format(&program, text),
+        Err(e) => return Err(format!("Error when format program: {}", e)),
+    };
+    if options.check && formatted != *text {
+        return Err(format!("{} is not formatted", path));
+    }
+    Ok(formatted)
+}
+
+fn fmt_file(path: &str, options: &Options) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    let formatted = match fmt_text(path, &text, options) {
+        Ok(formatted) => formatted,
+        Err(message) => {
+            println!("{}", message);
+            std::process::exit(1);
+        },
+    };
+    if options.check {
+        return Ok(());
+    }
+    match &options.output {
+        Some(output) => std::fs::write(output, formatted),
+        None => {
+            print!("{}", formatted);
+            Ok(())
+        },
+    }
+}
+
+fn translate_file(path: &str, translate: fn(&ASTArena, NodeId) -> String) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    match Program::parse(&text) {
+        Ok(program) => {
+            for line in notation::translate(&program.ast, program.root, translate) {
+                println!("{}", line);
+            }
+        },
+        Err(e) => println!("Error when translate program: {}", e),
+    }
+    Ok(())
+}
+
+fn json_text(text: &String, options: &Options) -> serde_json::Result<serde_json::Value> {
+    let tokens = tokenize(text);
+    let json = match Program::parse(text) {
+        Ok(mut program) => {
+            let ast = serde_json::to_value(&program.ast)?;
+            let root = program.root;
+            let (symbols, _) = SemanticAnalyzer::new().analyze(&program.ast, program.root);
+            let diagnostics = if options.passes.contains(&ir::Pass::FOLD) {
+                program.fold(options.arithmetic)
+            } else {
+                Vec::new()
+            };
+            serde_json::json!({
+                "tokens": tokens,
+                "ast": ast,
+                "root": root,
+                "slots": program.slots,
+                "symbols": symbols,
+                "diagnostics": diagnostics,
+            })
+        },
+        Err(e) => {
+            // errors take the same {span, message} shape as the diagnostics
+            let span = match &e {
+                InterpretError::SYNTAX(_, span) => *span,
+                InterpretError::RUNTIME(error) => error.span.unwrap_or_default(),
+            };
+            serde_json::json!({
+                "tokens": tokens,
+                "error": Diagnostic { span, message: e.to_string() },
+            })
+        },
+    };
+    Ok(json)
+}
+
+fn json_file(path: &str, options: &Options) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    let json = json_text(&text, options)?;
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(())
+}
+
+fn sexp_file(path: &str) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    match Program::parse(&text) {
+        Ok(program) => println!("{}", sexp::dump(&program.ast, program.root)),
+        Err(e) => println!("Error when parse program: {}", e),
+    }
+    Ok(())
+}
+
+fn annotate_file(path: &str) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    match Program::parse(&text) {
+        Ok(program) => print!("{}", annotate::annotate(&program)),
+        Err(e) => println!("Error when annotate program: {}", e),
+    }
+    Ok(())
+}
+
+fn emit_c_file(path: &str, options: &Options) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    let source = prepare(&text, options)
+        .map_err(|e| e.to_string())
+        .and_then(|program| emit_c::emit(&program, options.arithmetic));
+    match source {
+        Ok(source) => {
+            let output = match &options.output {
+                Some(output) => output.clone(),
+                None => std::path::Path::new(path).with_extension("c").to_string_lossy().into_owned(),
+            };
+            std::fs::write(output, source)
+        },
+        Err(e) => {
+            println!("Error when emit C: {}", e);
+            Ok(())
+        },
+    }
+}
+
+fn emit_wat_file(path: &str, options: &Options) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    let source = prepare(&text, options)
+        .map_err(|e| e.to_string())
+        .and_then(|program| emit_wat::emit(&program, options.arithmetic));
+    match source {
+        Ok(source) => {
+            let output = match &options.output {
+                Some(output) => output.clone(),
+                None => std::path::Path::new(path).with_extension("wat").to_string_lossy().into_owned(),
+            };
+            std::fs::write(output, source)
+        },
+        Err(e) => {
+            println!("Error when emit WebAssembly: {}", e);
+            Ok(())
+        },
+    }
+}
+
+fn native_file(path: &str, options: &Options) -> io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    let source = prepare(&text, options)
+        .map_err(|e| e.to_string())
+        .and_then(|program| emit_x86::emit(&program, options.arithmetic));
+    let source = match source {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Error when emit assembly: {}", e);
+            return Ok(());
+        },
+    };
+
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => std::path::Path::new(path).with_extension("").to_string_lossy().into_owned(),
+    };
+    let assembly = format!("{}.s", output);
+    std::fs::write(&assembly, source)?;
+    let status = std::process::Command::new("cc").arg(&assembly).arg("-o").arg(&output).status()?;
+    if !status.success() {
+        println!("Error when link {}: cc exited with {}", output, status);
+    }
+    Ok(())
+}
+
+fn disasm_file(path: &str, options: &Options) -> io::Result<()> {
+    let bytes = std::fs::read(path)?;
+    let chunk = if bytecode::Chunk::is_compiled(&bytes) {
+        bytecode::Chunk::from_bytes(&bytes)?
+    } else {
+        let text = String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        match prepare(&text, options) {
+            Ok(program) => compile(&program, options),
+            Err(e) => {
+                println!("Error when compile program: {}", e);
+                return Ok(());
+            },
+        }
+    };
+    print!("{}", chunk);
+    Ok(())
+}
+
+fn usage() {
+    println!("Usage: part-9 [OPTIONS]                      start the REPL");
+    println!("       part-9 [OPTIONS] run FILE             run a .pas source or .lbc program");
+    println!("       part-9 [-O] compile FILE [-o OUTPUT]  compile FILE to bytecode");
+    println!("       part-9 [-O] disasm FILE               print the bytecode listing of FILE");
+    println!("       part-9 ast [--dot] FILE               print the AST of FILE as an outline or Graphviz DOT");
+    println!("       part-9 parse-tree [--dot] FILE        print the concrete parse tree of FILE by grammar rule");
+    println!("       part-9 fmt [--check] FILE [-o OUTPUT] print FILE in canonical layout, or fail if it is not");
+    println!("       part-9 rpn FILE                       print each assignment of FILE in postfix notation");
+    println!("       part-9 lisp FILE                      print each assignment of FILE in LISP notation");
+    println!("       part-9 json FILE                      print the tokens, AST, symbols and warnings of FILE as JSON");
+    println!("       part-9 sexp FILE                      print the AST of FILE as an S-expression");
+    println!("       part-9 annotate FILE                  print FILE with scope levels and types on each name");
+    println!("       part-9 emit-c FILE [-o OUTPUT]        translate FILE to a standalone C program");
+    println!("       part-9 native FILE [-o OUTPUT]        build an x86-64 executable (keeps OUTPUT.s)");
+    println!("       part-9 emit-wat FILE [-o OUTPUT]      translate FILE to a WebAssembly text module");
+    println!();
+    println!("Options: [--backend=ast|vm] [--overflow=trap|wrap|saturate] [--integer=i32|i64|big]");
+    println!("         [--max-steps=N] [--max-depth=N] [--max-memory=BYTES] [--timeout=MS]");
+    println!("         [--input=NAME=VALUE,...]   run once per --input, each on its own thread");
+    println!("         [-O] [--no-fold] [--no-copy] [--no-cse] [--no-dce] [--dump-ir]");
+    println!("         [--trace]                  print each assignment as it runs, with what it reads and writes");
+    println!("         [--trace-parser]           log each parser rule entered and left to stderr");
+    println!();
+    println!("REPL:    :rpn EXPR | :lisp EXPR         translate EXPR to postfix or LISP notation");
+    println!("         :eval-rpn WORDS                 evaluate a postfix expression");
+    println!("         :explain EXPR                   evaluate EXPR one operator at a time, innermost first");
+}
+
+fn main() -> io::Result<()>{
+    let mut options = Options::new();
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--backend=ast" {
+            options.backend = Backend::AST;
+        } else if arg == "--backend=vm" {
+            options.backend = Backend::VM;
+        } else if let Some(mode) = arg.strip_prefix("--overflow=").and_then(OverflowMode::parse) {
+            options.arithmetic.overflow = mode;
+        } else if let Some(width) = arg.strip_prefix("--integer=").and_then(IntegerWidth::parse) {
+            options.arithmetic.width = width;
+        } else if let Some(Ok(n)) = arg.strip_prefix("--max-steps=").map(str::parse) {
+            options.limits.max_steps = Some(n);
+        } else if let Some(Ok(n)) = arg.strip_prefix("--max-depth=").map(str::parse) {
+            options.limits.max_depth = Some(n);
+        } else if let Some(Ok(n)) = arg.strip_prefix("--max-memory=").map(str::parse) {
+            options.limits.max_memory = Some(n);
+        } else if let Some(Ok(ms)) = arg.strip_prefix("--timeout=").map(str::parse) {
+            options.limits.timeout = Some(Duration::from_millis(ms));
+        } else if let Some(Some(inputs)) = arg.strip_prefix("--input=").map(parse_inputs) {
+            options.inputs.push(inputs);
+        } else if arg == "-O" {
+            options.optimize = true;
+        } else if let Some(pass) = arg.strip_prefix("--no-").and_then(ir::Pass::parse) {
+            options.passes.retain(|p| *p != pass);
+        } else if arg == "--dump-ir" {
+            options.dump_ir = true;
+        } else if arg == "--dot" {
+            options.dot = true;
+        } else if arg == "--check" {
+            options.check = true;
+        } else if arg == "--trace" {
+            options.trace = true;
+        } else if arg == "--trace-parser" {
+            options.trace_parser = true;
+        } else if arg == "-o" && options.output.is_none() {
+            options.output = args.next();
+        } else if arg.starts_with('-') {
+            usage();
+            return Ok(());
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    // only the tree-walking interpreter records a trace
+    if options.trace && options.backend == Backend::VM {
+        println!("--trace needs --backend=ast");
+        std::process::exit(1);
+    }
+
+    let positional: Vec<&str> = positional.iter().map(String::as_str).collect();
+    match positional.as_slice() {
+        [] => repl(&options),
+        ["run", path] => run_file(path, &options),
+        ["compile", path] => compile_file(path, &options),
+        ["disasm", path] => disasm_file(path, &options),
+        ["emit-c", path] => emit_c_file(path, &options),
+        ["annotate", path] => annotate_file(path),
+        ["json", path] => json_file(path, &options),
+        ["sexp", path] => sexp_file(path),
+        ["rpn", path] => translate_file(path, notation::rpn),
+        ["lisp", path] => translate_file(path, notation::lisp),
+        ["fmt", path] => fmt_file(path, &options),
+        ["ast", path] => ast_file(path, &options),
+        ["parse-tree", path] => parse_tree_file(path, &options),
+        ["native", path] => native_file(path, &options),
+        ["emit-wat", path] => emit_wat_file(path, &options),
+        _ => {
+            usage();
+            Ok(())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub const PROGRAMS: &[&str] = &[
+        "BEGIN END.",
+        "BEGIN a := 2; b := a * (3 + 4) / -2; BEGIN c := b - a END; END.",
+        "BEGIN a := -7; b := a / 2; c := 7 / -2; d := --a; e := +a - -a; f := (a - 1) * (a + 1) END.",
+        "BEGIN a := 7; b := a / (a - 7); c := 1 END.",
+        "BEGIN a := 1; b := a + 1; c := b / 0; d := 5 END.",
+        "BEGIN x := 1; y := x + z END.",
+        "BEGIN a := 2147483647; b := a + 1; c := -a - 1; d := c * -1; e := c / -1; f := -c; g := c - 1 END.",
+        "BEGIN a := 2147483647; b := a * a * a * 5 END.",
+        "BEGIN a := 9223372036854775807; m := -a - 1; d := m * -1; e := m / -1; f := -m; g := m - 1 END.",
+        "BEGIN a := 9223372036854775807; b := a + 1; c := 1 END.",
+        "{$Q-}\nBEGIN a := 9223372036854775807; b := a * 2; c := -b - a END.",
+        "BEGIN q := 99999999999999999999 / 1000000000000 END.",
+        "BEGIN a := 2; b := a * 3 + 1; c := a * 3 + 1; e := c; f := e + b; a := 10 END.",
+    ];
+
+    pub const ARITHMETICS: &[Arithmetic] = &[
+        Arithmetic { overflow: OverflowMode::TRAP, width: IntegerWidth::I64 },
+        Arithmetic { overflow: OverflowMode::WRAP, width: IntegerWidth::I64 },
+        Arithmetic { overflow: OverflowMode::SATURATE, width: IntegerWidth::I64 },
+        Arithmetic { overflow: OverflowMode::TRAP, width: IntegerWidth::I32 },
+        Arithmetic { overflow: OverflowMode::WRAP, width: IntegerWidth::I32 },
+        Arithmetic { overflow: OverflowMode::SATURATE, width: IntegerWidth::I32 },
+    ];
+
+    // what `run` prints for the program
+    pub fn output(text: &str, options: &Options) -> String {
+        match Executable::load(&text.to_string(), options) {
+            Ok(executable) => {
+                let mut runtime = options.runtime();
+                let result = executable.run(&mut runtime, &[]);
+                report(result, &mut runtime)
+            },
+            Err(e) => format!("Error when calculate expression: {}\n", e),
+        }
+    }
+
+    // a fresh directory per call, since the tests run in parallel
+    pub fn scratch() -> std::path::PathBuf {
+        static BUILDS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let build = BUILDS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("lsbasi-{}-{}", std::process::id(), build));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // builds `source` with cc and returns what the executable prints; cc is the linker rustc uses
+    // already, so a missing cc fails the test rather than skipping it
+    pub fn cc_output(source: &str, extension: &str) -> String {
+        let dir = scratch();
+        let file = dir.join(format!("program.{}", extension));
+        let executable = dir.join("program");
+        std::fs::write(&file, source).unwrap();
+        let status = std::process::Command::new("cc").arg(&file).arg("-o").arg(&executable).status();
+        let status = status.unwrap_or_else(|e| panic!("cannot run cc: {}", e));
+        assert!(status.success(), "cc failed on\n{}", source);
+        let run = std::process::Command::new(&executable).output().unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        String::from_utf8_lossy(&run.stdout).into_owned()
+    }
+
+    // a writer the test can read back what was written to, e.g. by a trace
+    #[derive(Clone)]
+    pub struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Shared {
+        pub fn new() -> Shared {
+            Shared(std::rc::Rc::new(std::cell::RefCell::new(Vec::new())))
+        }
+
+        pub fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub fn options(arithmetic: Arithmetic) -> Options {
+        let mut options = Options::new();
+        options.arithmetic = arithmetic;
+        options
+    }
+
+    #[test]
+    fn values_combine_by_type() {
+        let arithmetic = Arithmetic::new();
+        let cases = [
+            (Value::INT(7), CalcTokenType::DIV, Value::INT(2), Value::INT(3)),
+            (Value::INT(1), CalcTokenType::PLUS, Value::REAL(0.5), Value::REAL(1.5)),
+            (Value::REAL(3.0), CalcTokenType::DIV, Value::INT(2), Value::REAL(1.5)),
+            (Value::STRING(String::from("ab")), CalcTokenType::PLUS, Value::CHAR('c'), Value::STRING(String::from("abc"))),
+        ];
+        for (left, op, right, result) in cases {
+            assert_eq!(left.arith(op, &right, arithmetic).ok(), Some(result), "{} {} {}", left, op, right);
+        }
+    }
+
+    #[test]
+    fn type_errors() {
+        let arithmetic = Arithmetic::new();
+        let cases = [
+            (Value::INT(1), CalcTokenType::PLUS, Value::STRING(String::from("a")), "unsupported operand types for PLUS: INTEGER and STRING"),
+            (Value::BOOLEAN(true), CalcTokenType::MUL, Value::INT(2), "unsupported operand types for MUL: BOOLEAN and INTEGER"),
+            (Value::STRING(String::from("a")), CalcTokenType::MINUS, Value::CHAR('b'), "unsupported operand types for MINUS: STRING and CHAR"),
+            (Value::UNIT, CalcTokenType::DIV, Value::REAL(1.0), "unsupported operand types for DIV: UNIT and REAL"),
+        ];
+        for (left, op, right, message) in cases {
+            match left.arith(op, &right, arithmetic) {
+                Err(code @ ErrorCode::TYPEMISMATCH { .. }) => assert_eq!(code.to_string(), format!("Type error: {}", message)),
+                other => panic!("{} {} {} gave {:?}", left, op, right, other),
+            }
+        }
+
+        let unary = [
+            (Value::STRING(String::from("a")).neg(arithmetic), "bad operand type for unary MINUS: STRING"),
+            (Value::BOOLEAN(false).pos(), "bad operand type for unary PLUS: BOOLEAN"),
+        ];
+        for (result, message) in unary {
+            match result {
+                Err(code @ ErrorCode::BADOPERAND { .. }) => assert_eq!(code.to_string(), format!("Type error: {}", message)),
+                other => panic!("{} gave {:?}", message, other),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_characters_are_rejected() {
+        let cases = [
+            ("BEGIN a :", "line 1, column 9"),
+            ("BEGIN a := 2 @ END.", "line 1, column 14"),
+            ("BEGIN a := 2 END. ?", "line 1, column 19"),
+        ];
+        for (text, span) in cases {
+            let expected = format!("Error when calculate expression: Invalid syntax at {}: 'Z'\n", span);
+            assert_eq!(output(text, &Options::new()), expected, "{}", text);
         }
-        io::stdout().flush().unwrap();
     }
 
+    #[test]
+    fn overflow_modes() {
+        let text = "BEGIN a := 9223372036854775807; b := a + 1; c := -a - 2; d := a * 2 END.";
+        let cases = [
+            (OverflowMode::TRAP, "Error when calculate expression: Runtime error at line 1, column 40: Integer overflow in PLUS\n\
+                                  CALL STACK (most recent call first)\n1: PROGRAM main\n   a                   : 9223372036854775807\n"),
+            (OverflowMode::WRAP, "a: 9223372036854775807\nb: -9223372036854775808\nc: 9223372036854775807\nd: -2\n"),
+            (OverflowMode::SATURATE, "a: 9223372036854775807\nb: 9223372036854775807\nc: -9223372036854775808\nd: 9223372036854775807\n"),
+        ];
+        for (overflow, expected) in cases {
+            let arithmetic = Arithmetic { overflow, width: IntegerWidth::I64 };
+            assert_eq!(output(text, &options(arithmetic)), expected, "{:?}", overflow);
+        }
+    }
+
+    #[test]
+    fn division_by_zero_in_every_mode() {
+        for &arithmetic in ARITHMETICS {
+            assert_eq!(
+                output("BEGIN a := 1;\nb := a / (a - 1) END.", &options(arithmetic)),
+                "Error when calculate expression: Runtime error at line 2, column 8: Division by zero\n\
+                 CALL STACK (most recent call first)\n1: PROGRAM main\n   a                   : 1\n",
+                "{:?}", arithmetic,
+            );
+        }
+    }
+
+    #[test]
+    fn directives_override_the_command_line() {
+        let trapped = "Error when calculate expression: Runtime error at line 2, column 23: Integer overflow in PLUS\n\
+                       CALL STACK (most recent call first)\n1: PROGRAM main\n";
+        let cases = [
+            ("{$Q-}", OverflowMode::TRAP, "a: -2147483648\n"),
+            ("{$Q-}", OverflowMode::SATURATE, "a: -2147483648\n"),
+            ("{$Q+}", OverflowMode::WRAP, trapped),
+            ("{$Q+}", OverflowMode::SATURATE, trapped),
+        ];
+        for (directive, overflow, expected) in cases {
+            let text = format!("{}\nBEGIN a := 2147483647 + 1 END.", directive);
+            let arithmetic = Arithmetic { overflow, width: IntegerWidth::I32 };
+            for backend in [Backend::AST, Backend::VM] {
+                let mut options = options(arithmetic);
+                options.backend = backend;
+                assert_eq!(output(&text, &options), expected, "{} over {:?}", directive, overflow);
+            }
+        }
+    }
+
+    #[test]
+    fn integer_widths() {
+        let text = "BEGIN a := 3000000000; b := a * a * a END.";
+        let cases = [
+            (IntegerWidth::I32, "Error when calculate expression: Runtime error at line 1, column 12: Integer literal 3000000000 is out of range\n\
+                                 CALL STACK (most recent call first)\n1: PROGRAM main\n"),
+            (IntegerWidth::I64, "Error when calculate expression: Runtime error at line 1, column 35: Integer overflow in MUL\n\
+                                 CALL STACK (most recent call first)\n1: PROGRAM main\n   a                   : 3000000000\n"),
+            (IntegerWidth::BIG, "a: 3000000000\nb: 27000000000000000000000000000\n"),
+        ];
+        for (width, expected) in cases {
+            let arithmetic = Arithmetic { overflow: OverflowMode::TRAP, width };
+            for backend in [Backend::AST, Backend::VM] {
+                let mut options = options(arithmetic);
+                options.backend = backend;
+                assert_eq!(output(text, &options), expected, "{:?}", width);
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_literals() {
+        // the lexer keeps a literal too big for i64 whole, and only the widths that cannot hold it reject it
+        let text = String::from("99999999999999999999");
+        let token = Lexer::new(&text).get_next_token();
+        assert_eq!(token.value, Some(Value::BIGINT("99999999999999999999".parse().unwrap())));
+
+        let text = "BEGIN a := 99999999999999999999 - 99999999999999999998 END.";
+        for width in [IntegerWidth::I32, IntegerWidth::I64] {
+            assert_eq!(
+                output(text, &options(Arithmetic { overflow: OverflowMode::WRAP, width })),
+                "Error when calculate expression: Runtime error at line 1, column 12: Integer literal 99999999999999999999 is out of range\n\
+                 CALL STACK (most recent call first)\n1: PROGRAM main\n",
+                "{:?}", width,
+            );
+        }
+        assert_eq!(output(text, &options(Arithmetic { overflow: OverflowMode::TRAP, width: IntegerWidth::BIG })), "a: 1\n");
+    }
+
+    #[test]
+    fn call_stack_is_rendered_innermost_first() {
+        let mut main = ActivationRecord::new("main", ARType::PROGRAM, 1, None, vec![String::from("a"), String::from("b")]);
+        main.set(0, Value::INT(1));
+        let mut inner = ActivationRecord::new("inner", ARType::PROGRAM, 2, Some(3), vec![String::from("x"), String::from("y")]);
+        inner.set(1, Value::STRING(String::from("text")));
+        let mut error = RuntimeError::new(ErrorCode::DIVISIONBYZERO, Span { line: 4, column: 9 });
+        error.stack = vec![main, inner];
+        // members without a value are left out
+        assert_eq!(InterpretError::RUNTIME(error).to_string(), "\
+Runtime error at line 4, column 9: Division by zero
+CALL STACK (most recent call first)
+2: PROGRAM inner (called from line 3)
+   y                   : text
+1: PROGRAM main
+   a                   : 1");
+
+        let error = RuntimeError::unspanned(ErrorCode::LIMITEXCEEDED(Limit::STEPS(1)));
+        assert_eq!(InterpretError::RUNTIME(error).to_string(), "Runtime error: Execution limit exceeded: more than 1 statements executed");
+    }
+
+    #[test]
+    fn failing_program_reports_its_frame() {
+        let text = "BEGIN a := 1;\nb := a + 1;\nc := b + z END.";
+        for backend in [Backend::AST, Backend::VM] {
+            let mut options = Options::new();
+            options.backend = backend;
+            assert_eq!(
+                output(text, &options),
+                "Error when calculate expression: Runtime error at line 3, column 10: Undefined variable: z\n\
+                 CALL STACK (most recent call first)\n1: PROGRAM main\n   a                   : 1\n   b                   : 2\n",
+            );
+        }
+    }
+
+    #[test]
+    fn variables_resolve_to_slots() {
+        let program = Program::parse(&String::from("BEGIN b := 1; a := b; BEGIN b := a + c END END.")).unwrap_or_else(|e| panic!("{}", e));
+        // slots are handed out in order of first appearance, reads of an unassigned name included
+        assert_eq!(program.globals, ["b", "a", "c"]);
+        let mut resolved = Vec::new();
+        for (id, node) in program.ast.nodes.iter().enumerate() {
+            match node {
+                AST::VAR(var) => resolved.push((var.name(), program.slots[NodeId(id)])),
+                _ => assert!(program.slots.get(NodeId(id)).is_none(), "node {} has a slot", id),
+            }
+        }
+        let expected = [("b", (0, 0)), ("a", (0, 1)), ("b", (0, 0)), ("b", (0, 0)), ("a", (0, 1)), ("c", (0, 2))];
+        let expected: Vec<(String, (usize, usize))> = expected.iter().map(|(name, slot)| (name.to_string(), *slot)).collect();
+        assert_eq!(resolved, expected);
+    }
+
+    #[test]
+    fn frames_are_slot_vectors() {
+        let program = Program::parse(&String::from("BEGIN b := 1; a := b + 1 END.")).unwrap_or_else(|e| panic!("{}", e));
+        let mut runtime = Options::new().runtime();
+        assert!(program.run(&mut runtime, &[]).is_ok());
+        let frame = runtime.call_stack.peek();
+        assert_eq!(frame.names, ["b", "a"]);
+        assert_eq!(frame.slots, [Some(Value::INT(1)), Some(Value::INT(2))]);
+        // members are listed in slot order, not by name
+        assert_eq!(report(Ok(Value::UNIT), &mut runtime), "b: 1\na: 2\n");
+    }
+
+    #[test]
+    fn arena_holds_children_before_parents() {
+        let program = Program::parse(&String::from("BEGIN a := -(1 + 2) * 3; BEGIN b := a END; END.")).unwrap_or_else(|e| panic!("{}", e));
+        let nodes = &program.ast.nodes;
+        assert_eq!(program.root, NodeId(nodes.len() - 1));
+        for (id, node) in nodes.iter().enumerate() {
+            let children = match node {
+                AST::BINOP(node) => vec![node.left, node.right],
+                AST::UNARYOP(node) => vec![node.expr],
+                AST::ASSIGN(node) => vec![node.left, node.right],
+                AST::COMPOUND(node) => node.children.clone(),
+                _ => Vec::new(),
+            };
+            assert!(children.iter().all(|child| child.0 < id), "node {} comes before a child", id);
+        }
+        let compounds = nodes.iter().filter(|node| matches!(node, AST::COMPOUND(_))).count();
+        assert_eq!((nodes.len(), compounds), (14, 2));
+    }
+
+    #[test]
+    fn side_table_entries() {
+        let mut table = SideTable::new();
+        table.insert(NodeId(3), "three");
+        table.insert(NodeId(1), "one");
+        let entries: Vec<Option<&&str>> = (0..5).map(|id| table.get(NodeId(id))).collect();
+        assert_eq!(entries, [None, Some(&"one"), None, Some(&"three"), None]);
+        assert_eq!(table[NodeId(3)], "three");
+    }
+
+    #[test]
+    fn folding_rewrites_nodes_in_place() {
+        let mut program = Program::parse(&String::from("BEGIN a := 2 * 3 + x END.")).unwrap_or_else(|e| panic!("{}", e));
+        let (root, count) = (program.root, program.ast.nodes.len());
+        assert!(program.fold(Arithmetic::new()).is_empty());
+        // 2 * 3 becomes a literal in its own node; nothing is added or moved
+        assert_eq!((program.root, program.ast.nodes.len()), (root, count));
+        assert_eq!(notation::translate(&program.ast, program.root, notation::lisp), ["a := (+ 6 x)"]);
+    }
+
+    #[test]
+    fn chunk_matches_tree() {
+        let big = Arithmetic { overflow: OverflowMode::TRAP, width: IntegerWidth::BIG };
+        for &arithmetic in ARITHMETICS.iter().chain([&big]) {
+            for text in PROGRAMS {
+                let tree = output(text, &options(arithmetic));
+                for optimize in [false, true] {
+                    let mut options = options(arithmetic);
+                    options.backend = Backend::VM;
+                    options.optimize = optimize;
+                    assert_eq!(output(text, &options), tree, "{} with {:?}, -O {}", text, arithmetic, optimize);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn saved_chunk_matches_tree() {
+        for &arithmetic in ARITHMETICS {
+            for text in PROGRAMS {
+                let options = options(arithmetic);
+                let chunk = compile(&prepare(&text.to_string(), &options).unwrap_or_else(|e| panic!("{}", e)), &options);
+                let loaded = Executable::CHUNK(bytecode::Chunk::from_bytes(&chunk.to_bytes().unwrap()).unwrap());
+                // the chunk carries its own arithmetic, so it runs the same under default options
+                let mut runtime = Options::new().runtime();
+                let result = loaded.run(&mut runtime, &[]);
+                assert_eq!(report(result, &mut runtime), output(text, &options), "{} with {:?}", text, arithmetic);
+            }
+        }
+    }
+
+    #[test]
+    fn inputs_are_integers() {
+        let inputs = parse_inputs("x=3, y = 99999999999999999999").unwrap();
+        assert_eq!(inputs, vec![
+            (String::from("x"), Value::INT(3)),
+            (String::from("y"), Value::BIGINT("99999999999999999999".parse().unwrap())),
+        ]);
+        for text in ["x=1.5", "x=1e3", "x=", "x"] {
+            assert_eq!(parse_inputs(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn program_runs_on_threads() {
+        for (backend, name) in [(Backend::AST, "ast"), (Backend::VM, "vm")] {
+            let mut options = Options::new();
+            options.backend = backend;
+            let executable = Executable::load(&String::from("BEGIN y := x * 2 END."), &options).unwrap_or_else(|e| panic!("{}", e));
+            let inputs = [parse_inputs("x=3").unwrap(), parse_inputs("x=5").unwrap()];
+            let outputs: Vec<String> = std::thread::scope(|scope| {
+                let handles: Vec<_> = inputs.iter().map(|inputs| {
+                    scope.spawn(|| {
+                        let mut runtime = options.runtime();
+                        let result = executable.run(&mut runtime, inputs);
+                        report(result, &mut runtime)
+                    })
+                }).collect();
+                handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+            });
+            assert_eq!(outputs, ["x: 3\ny: 6\n", "x: 5\ny: 10\n"], "{}", name);
+        }
+    }
+
+    #[test]
+    fn syntax_error_shows_span() {
+        assert_eq!(output("BEGIN a := (2 END.", &Options::new()), "Error when calculate expression: Invalid syntax at line 1, column 15: 'Z'\n");
+    }
+
+    #[test]
+    fn json_shape() {
+        let json = json_text(&String::from("BEGIN a := 2; b := a END."), &Options::new()).unwrap();
+        let keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["ast", "diagnostics", "root", "slots", "symbols", "tokens"]);
+        // slots maps the id of each VAR node to its (depth, slot)
+        assert_eq!(json["slots"], serde_json::json!({ "0": [0, 0], "3": [0, 1], "4": [0, 0] }));
+        assert_eq!(json["ast"]["nodes"][3]["VAR"]["token"]["value"], serde_json::json!({ "STRING": "b" }));
+        assert_eq!(json["root"], serde_json::json!(6));
+        assert_eq!(json["symbols"]["symbols"], serde_json::json!([{ "name": "a", "slot": 0 }, { "name": "b", "slot": 1 }]));
+        assert_eq!(json["tokens"][1], serde_json::json!({
+            "genre": "ID",
+            "span": { "line": 1, "column": 7 },
+            "trivia": [{ "WHITESPACE": " " }],
+            "value": { "STRING": "a" },
+        }));
+    }
+
+    #[test]
+    fn json_diagnostics_and_errors() {
+        let json = json_text(&String::from("BEGIN a := 4 / (2 - 2) END."), &Options::new()).unwrap();
+        assert_eq!(json["diagnostics"], serde_json::json!([{
+            "span": { "line": 1, "column": 14 },
+            "message": "Division by zero",
+        }]));
+
+        let json = json_text(&String::from("BEGIN a := (2 END."), &Options::new()).unwrap();
+        let keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["error", "tokens"]);
+        assert_eq!(json["error"], serde_json::json!({
+            "span": { "line": 1, "column": 15 },
+            "message": "Invalid syntax at line 1, column 15: 'Z'",
+        }));
+    }
+
+    // the runtime error `text` stops with under `limits`, on both backends
+    fn limited(text: &str, limits: Limits, width: IntegerWidth) -> Vec<RuntimeError> {
+        [Backend::AST, Backend::VM].into_iter().map(|backend| {
+            let mut options = Options::new();
+            options.limits = limits;
+            options.backend = backend;
+            options.arithmetic.width = width;
+            let executable = Executable::load(&text.to_string(), &options).unwrap_or_else(|e| panic!("{}", e));
+            match executable.run(&mut options.runtime(), &[]) {
+                Err(InterpretError::RUNTIME(error)) => error,
+                _ => panic!("{} ran within {:?}", text, limits),
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn step_limit() {
+        let limits = Limits { max_steps: Some(2), ..Limits::default() };
+        for error in limited("BEGIN a := 1; b := 2; c := 3 END.", limits, IntegerWidth::I64) {
+            assert!(matches!(error.code, ErrorCode::LIMITEXCEEDED(Limit::STEPS(2))), "{}", error);
+            assert_eq!(error.span, Some(Span { line: 1, column: 17 }));
+        }
+    }
+
+    #[test]
+    fn depth_limit() {
+        let limits = Limits { max_depth: Some(0), ..Limits::default() };
+        for error in limited("BEGIN a := 1 END.", limits, IntegerWidth::I64) {
+            assert!(matches!(error.code, ErrorCode::LIMITEXCEEDED(Limit::DEPTH(0))), "{}", error);
+            assert_eq!(error.span, None);
+        }
+    }
+
+    #[test]
+    fn memory_limit() {
+        let limits = Limits { max_memory: Some(12), ..Limits::default() };
+        // only the names and the BIGINT payload count, not the slots the values sit in
+        let mut options = Options::new();
+        options.limits = limits;
+        assert_eq!(output("BEGIN a := 2; b := a * 1000 END.", &options), "a: 2\nb: 2000\n");
+        for error in limited("BEGIN a := 2; s := 99999999999999999999999999 END.", limits, IntegerWidth::BIG) {
+            assert!(matches!(error.code, ErrorCode::LIMITEXCEEDED(Limit::MEMORY(12))), "{}", error);
+            assert_eq!(error.span, Some(Span { line: 1, column: 17 }));
+            assert!(error.stack[0].get(1).is_none());
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let limits = Limits { timeout: Some(Duration::ZERO), ..Limits::default() };
+        for error in limited("BEGIN a := 1 END.", limits, IntegerWidth::I64) {
+            assert!(matches!(error.code, ErrorCode::LIMITEXCEEDED(Limit::TIMEOUT(Duration::ZERO))), "{}", error);
+            assert_eq!(error.span, None);
+        }
+    }
 }
@@ -0,0 +1,227 @@
+use super::visit::{self, Visitor};
+use super::{AST, ASTArena, Arithmetic, Assign, BinOp, CalcTokenType, ErrorCode, NodeId, Num, UnaryOp, Value, Var};
+
+fn symbol(op: CalcTokenType) -> &'static str {
+    match op {
+        CalcTokenType::PLUS => "+",
+        CalcTokenType::MINUS => "-",
+        CalcTokenType::MUL => "*",
+        _ => "/",
+    }
+}
+
+pub struct RPNTranslator {
+    words: Vec<String>,
+}
+
+impl RPNTranslator {
+    pub fn new() -> RPNTranslator {
+        RPNTranslator {
+            words: Vec::new(),
+        }
+    }
+
+    pub fn translate(mut self, ast: &ASTArena, id: NodeId) -> String {
+        self.visit(ast, id);
+        self.words.join(" ")
+    }
+}
+
+impl Visitor for RPNTranslator {
+    type Result = ();
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) {
+        visit::walk_binop(self, ast, node);
+        self.words.push(symbol(node.op.genre).to_string());
+    }
+
+    // unary operators get their own words so that `-` stays binary for the evaluator
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) {
+        visit::walk_unaryop(self, ast, node);
+        match node.op.genre {
+            CalcTokenType::MINUS => self.words.push(String::from("neg")),
+            _ => self.words.push(String::from("pos")),
+        }
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) {
+        self.words.push(node.token.value.clone().unwrap_or(Value::UNIT).to_string());
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, _id: NodeId, node: &Var) {
+        self.words.push(node.name());
+    }
+}
+
+pub struct LISPTranslator {
+    stack: Vec<String>,
+}
+
+impl LISPTranslator {
+    pub fn new() -> LISPTranslator {
+        LISPTranslator {
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn translate(mut self, ast: &ASTArena, id: NodeId) -> String {
+        self.visit(ast, id);
+        self.stack.pop().unwrap_or_default()
+    }
+}
+
+impl Visitor for LISPTranslator {
+    type Result = ();
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) {
+        visit::walk_binop(self, ast, node);
+        let right = self.stack.pop().unwrap_or_default();
+        let left = self.stack.pop().unwrap_or_default();
+        self.stack.push(format!("({} {} {})", symbol(node.op.genre), left, right));
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) {
+        visit::walk_unaryop(self, ast, node);
+        let operand = self.stack.pop().unwrap_or_default();
+        self.stack.push(format!("({} {})", symbol(node.op.genre), operand));
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, node: &Num) {
+        self.stack.push(node.token.value.clone().unwrap_or(Value::UNIT).to_string());
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, _id: NodeId, node: &Var) {
+        self.stack.push(node.name());
+    }
+}
+
+struct Assignments {
+    translate: fn(&ASTArena, NodeId) -> String,
+    lines: Vec<String>,
+}
+
+impl Visitor for Assignments {
+    type Result = ();
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) {
+        if let AST::VAR(var) = &ast[node.left] {
+            self.lines.push(format!("{} := {}", var.name(), (self.translate)(ast, node.right)));
+        }
+    }
+}
+
+pub fn rpn(ast: &ASTArena, id: NodeId) -> String {
+    RPNTranslator::new().translate(ast, id)
+}
+
+pub fn lisp(ast: &ASTArena, id: NodeId) -> String {
+    LISPTranslator::new().translate(ast, id)
+}
+
+// one `name := expression` line per assignment of the program, in execution order
+pub fn translate(ast: &ASTArena, root: NodeId, translate: fn(&ASTArena, NodeId) -> String) -> Vec<String> {
+    let mut assignments = Assignments {
+        translate,
+        lines: Vec::new(),
+    };
+    assignments.visit(ast, root);
+    assignments.lines
+}
+
+pub fn eval_rpn(text: &str, arithmetic: Arithmetic) -> Result<Value, String> {
+    let mut stack: Vec<Value> = Vec::new();
+    for word in text.split_whitespace() {
+        let op = match word {
+            "+" => Some(CalcTokenType::PLUS),
+            "-" => Some(CalcTokenType::MINUS),
+            "*" => Some(CalcTokenType::MUL),
+            "/" => Some(CalcTokenType::DIV),
+            _ => None,
+        };
+        let value = if let Some(op) = op {
+            match (stack.pop(), stack.pop()) {
+                (Some(right), Some(left)) => left.arith(op, &right, arithmetic),
+                _ => return Err(format!("Stack underflow at '{}'", word)),
+            }
+        } else if word == "neg" || word == "pos" {
+            match stack.pop() {
+                Some(operand) if word == "neg" => operand.neg(arithmetic),
+                Some(operand) => operand.pos(),
+                None => return Err(format!("Stack underflow at '{}'", word)),
+            }
+        } else if word.chars().all(|c| c.is_ascii_digit()) {
+            let value = match word.parse::<i64>() {
+                Ok(n) => Value::INT(n),
+                Err(_) => Value::BIGINT(word.parse().unwrap_or_default()),
+            };
+            arithmetic.literal(value)
+        } else if word.chars().all(char::is_alphanumeric) {
+            Err(ErrorCode::UNDEFINEDVARIABLE(word.to_string()))
+        } else {
+            return Err(format!("Unknown word '{}'", word));
+        };
+        stack.push(value.map_err(|code| code.to_string())?);
+    }
+
+    match stack.len() {
+        1 => Ok(stack.pop().unwrap()),
+        n => Err(format!("Expected one result, found {}", n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{eval_rpn, lisp, rpn};
+    use crate::{ASTArena, Arithmetic, IntegerWidth, Lexer, NodeId, OverflowMode, Parser, Program, Runtime, Value};
+
+    fn translate(text: &str, translate: fn(&ASTArena, NodeId) -> String) -> String {
+        let text = text.to_string();
+        let mut parser = Parser::new(Lexer::new(&text));
+        let id = parser.parse_expr().unwrap_or_else(|e| panic!("{:?}", e));
+        translate(&parser.ast, id)
+    }
+
+    fn interpret(text: &str, arithmetic: Arithmetic) -> Option<Value> {
+        let program = Program::parse(&format!("BEGIN x := {} END.", text)).unwrap_or_else(|e| panic!("{}", e));
+        let mut runtime = Runtime::new();
+        runtime.arithmetic = arithmetic;
+        program.run(&mut runtime, &[]).ok()?;
+        runtime.lookup(0, 0).ok()
+    }
+
+    fn round_trip(arithmetic: Arithmetic) {
+        let expressions = [
+            "-3", "+4", "- -5", "-(2 + 3)", "- - 2 * -3", "-(-(7))",
+            "((1 + 2) * (3 - 4)) / 2", "2 * (3 + (4 - (5 * 6)))", "-(1 - (2 - (3 - 4)))",
+            "10 - 4 - 3", "7 - 2 - -1", "1 - (2 - 3)", "100 / 10 / 5", "64 / 4 / 2 / 2", "20 / (10 / 2)", "9 / 2 - 7 / -2",
+            "1 / 0", "5 - 5 / (3 - 3)", "2147483647 + 1", "-2147483647 - 2", "9223372036854775807 + 1",
+        ];
+        for text in expressions {
+            let words = translate(text, rpn);
+            assert_eq!(eval_rpn(&words, arithmetic).ok(), interpret(text, arithmetic), "{} as {}", text, words);
+        }
+    }
+
+    #[test]
+    fn rpn_round_trip_matches_interpreter() {
+        round_trip(Arithmetic::new());
+        round_trip(Arithmetic { overflow: OverflowMode::WRAP, width: IntegerWidth::I32 });
+        round_trip(Arithmetic { overflow: OverflowMode::SATURATE, width: IntegerWidth::I64 });
+        round_trip(Arithmetic { overflow: OverflowMode::TRAP, width: IntegerWidth::BIG });
+    }
+
+    #[test]
+    fn rpn_words() {
+        assert_eq!(translate("10 - 4 - 3", rpn), "10 4 - 3 -");
+        assert_eq!(translate("-(2 + x) * +3", rpn), "2 x + neg 3 pos *");
+    }
+
+    #[test]
+    fn lisp_golden() {
+        assert_eq!(translate("1 + 2 * 3", lisp), "(+ 1 (* 2 3))");
+        assert_eq!(translate("10 - 4 - 3", lisp), "(- (- 10 4) 3)");
+        assert_eq!(translate("100 / (10 / 5)", lisp), "(/ 100 (/ 10 5))");
+        assert_eq!(translate("-(a - b) / +c", lisp), "(/ (- (- a b)) (+ c))");
+        assert_eq!(translate("- - 2", lisp), "(- (- 2))");
+    }
+}
@@ -0,0 +1,93 @@
+use std::io::Write;
+
+use super::{CalcTokenType, ParseListener, Token};
+
+// logs every rule the parser enters and leaves, indented by nesting depth
+pub struct ParseTracer {
+    depth: usize,
+    out: Box<dyn Write>,
+}
+
+impl ParseTracer {
+    pub fn new(out: Box<dyn Write>) -> ParseTracer {
+        ParseTracer {
+            depth: 0,
+            out,
+        }
+    }
+
+    fn log(&mut self, event: &str, token: &Token) {
+        writeln!(self.out, "{:>3} {}{} {} at {}", self.depth, "  ".repeat(self.depth), event, token, token.span).ok();
+    }
+}
+
+impl ParseListener for ParseTracer {
+    fn enter(&mut self, rule: &'static str, token: &Token) {
+        self.log(&format!("enter {}", rule), token);
+        self.depth += 1;
+    }
+
+    fn exit(&mut self, rule: &'static str, token: &Token, ok: bool) {
+        self.depth -= 1;
+        let event = if ok { "exit" } else { "fail" };
+        self.log(&format!("{} {}", event, rule), token);
+    }
+
+    fn consume(&mut self, token: &Token) {
+        self.log("eat", token);
+    }
+
+    fn mismatch(&mut self, expected: CalcTokenType, token: &Token) {
+        self.log(&format!("expected {}, found", expected), token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParseTracer;
+    use crate::tests::Shared;
+    use crate::{Lexer, Parser};
+
+    // what the tracer logs while parsing `text`, and whether the parse succeeded
+    fn traced(text: &str) -> (String, bool) {
+        let shared = Shared::new();
+        let mut tracer = ParseTracer::new(Box::new(shared.clone()));
+        let text = text.to_string();
+        let parsed = Parser::with_listener(Lexer::new(&text), &mut tracer).parse().is_ok();
+        (shared.text(), parsed)
+    }
+
+    #[test]
+    fn rules_are_entered_and_left() {
+        let trace = "  0 enter program Token(BEGIN, BEGIN) at line 1, column 1
+  1   enter compound_statement Token(BEGIN, BEGIN) at line 1, column 1
+  2     eat Token(BEGIN, BEGIN) at line 1, column 1
+  2     enter statement_list Token(END, END) at line 1, column 7
+  3       enter statement Token(END, END) at line 1, column 7
+  4         enter empty Token(END, END) at line 1, column 7
+  4         exit empty Token(END, END) at line 1, column 7
+  3       exit statement Token(END, END) at line 1, column 7
+  2     exit statement_list Token(END, END) at line 1, column 7
+  2     eat Token(END, END) at line 1, column 7
+  1   exit compound_statement Token(., .) at line 1, column 10
+  1   eat Token(., .) at line 1, column 10
+  0 exit program Token(EOF, None) at line 1, column 11
+";
+        assert_eq!(traced("BEGIN END."), (String::from(trace), true));
+    }
+
+    #[test]
+    fn failed_rules_unwind() {
+        let (trace, parsed) = traced("BEGIN a := (2 END.");
+        assert!(!parsed);
+        let lines: Vec<&str> = trace.lines().collect();
+        let mismatch = lines.iter().position(|line| line.ends_with("expected ), found Token(END, END) at line 1, column 15"));
+        let mismatch = mismatch.unwrap_or_else(|| panic!("no mismatch in\n{}", trace));
+        assert_eq!(lines[mismatch + 1..mismatch + 4], [
+            "  7               fail factor Token(END, END) at line 1, column 15",
+            "  6             fail term Token(END, END) at line 1, column 15",
+            "  5           fail expr Token(END, END) at line 1, column 15",
+        ]);
+        assert_eq!(lines.last(), Some(&"  0 fail program Token(END, END) at line 1, column 15"));
+    }
+}
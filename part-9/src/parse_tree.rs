@@ -0,0 +1,178 @@
+use super::{CalcTokenType, InterpretError, Lexer, ParseListener, Parser, Token};
+
+pub enum ParseNode {
+    RULE(&'static str, Vec<ParseNode>),
+    TOKEN(Token),
+}
+
+impl ParseNode {
+    fn label(&self) -> String {
+        match self {
+            ParseNode::RULE(rule, _) => rule.to_string(),
+            ParseNode::TOKEN(token) => match &token.value {
+                Some(value) => value.to_string(),
+                None => token.genre.to_string(),
+            },
+        }
+    }
+
+    pub fn outline(&self, indent: usize) -> String {
+        let mut text = format!("{}{}\n", "  ".repeat(indent), self.label());
+        if let ParseNode::RULE(_, children) = self {
+            for child in children {
+                text += &child.outline(indent + 1);
+            }
+        }
+        text
+    }
+
+    pub fn gendot(&self) -> String {
+        let mut dot = String::from("digraph astgraph {\n");
+        dot += "  node [shape=none, fontsize=12, fontname=\"Courier\", height=.1];\n";
+        dot += "  ranksep=.3;\n";
+        dot += "  edge [arrowsize=.5]\n\n";
+        self.dot_node(&mut 1, &mut dot);
+        dot += "}\n";
+        dot
+    }
+
+    fn dot_node(&self, ncount: &mut usize, dot: &mut String) -> usize {
+        let num = *ncount;
+        *ncount += 1;
+        *dot += &format!("  node{} [label=\"{}\"]\n", num, self.label().replace('"', "\\\""));
+        if let ParseNode::RULE(_, children) = self {
+            for child in children {
+                let child = child.dot_node(ncount, dot);
+                *dot += &format!("  node{} -> node{}\n", num, child);
+            }
+        }
+        num
+    }
+}
+
+struct ParseTreeBuilder {
+    stack: Vec<(&'static str, Vec<ParseNode>)>,
+    root: Option<ParseNode>,
+}
+
+impl ParseTreeBuilder {
+    fn new() -> ParseTreeBuilder {
+        ParseTreeBuilder {
+            stack: Vec::new(),
+            root: None,
+        }
+    }
+}
+
+impl ParseListener for ParseTreeBuilder {
+    fn enter(&mut self, rule: &'static str, _token: &Token) {
+        self.stack.push((rule, Vec::new()));
+    }
+
+    fn exit(&mut self, _rule: &'static str, _token: &Token, _ok: bool) {
+        if let Some((rule, children)) = self.stack.pop() {
+            let node = ParseNode::RULE(rule, children);
+            match self.stack.last_mut() {
+                Some((_, siblings)) => siblings.push(node),
+                None => self.root = Some(node),
+            }
+        }
+    }
+
+    fn consume(&mut self, token: &Token) {
+        if let Some((_, children)) = self.stack.last_mut() {
+            children.push(ParseNode::TOKEN(token.clone()));
+        }
+    }
+
+    fn mismatch(&mut self, _expected: CalcTokenType, _token: &Token) {}
+}
+
+// the concrete syntax tree of `text`, with every rule the parser went through and every token it ate
+pub fn parse(text: &String) -> Result<Option<ParseNode>, InterpretError> {
+    let mut builder = ParseTreeBuilder::new();
+    let mut parser = Parser::with_listener(Lexer::new(text), &mut builder);
+    parser.parse().map_err(|e| InterpretError::SYNTAX(e, parser.span()))?;
+    Ok(builder.root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParseNode, parse};
+
+    fn tree(text: &str) -> ParseNode {
+        match parse(&text.to_string()) {
+            Ok(Some(root)) => root,
+            Ok(None) => panic!("no tree for {}", text),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn every_rule_and_token_is_kept() {
+        assert_eq!(tree("BEGIN a := -(2 + b) END.").outline(0), "\
+program
+  compound_statement
+    BEGIN
+    statement_list
+      statement
+        assignment_statement
+          variable
+            a
+          :=
+          expr
+            term
+              factor
+                -
+                factor
+                  (
+                  expr
+                    term
+                      factor
+                        2
+                    +
+                    term
+                      factor
+                        variable
+                          b
+                  )
+    END
+  .
+");
+    }
+
+    #[test]
+    fn dot_graph() {
+        assert_eq!(tree("BEGIN END.").gendot(), "\
+digraph astgraph {
+  node [shape=none, fontsize=12, fontname=\"Courier\", height=.1];
+  ranksep=.3;
+  edge [arrowsize=.5]
+
+  node1 [label=\"program\"]
+  node2 [label=\"compound_statement\"]
+  node3 [label=\"BEGIN\"]
+  node2 -> node3
+  node4 [label=\"statement_list\"]
+  node5 [label=\"statement\"]
+  node6 [label=\"empty\"]
+  node5 -> node6
+  node4 -> node5
+  node2 -> node4
+  node7 [label=\"END\"]
+  node2 -> node7
+  node1 -> node2
+  node8 [label=\".\"]
+  node1 -> node8
+}
+");
+    }
+
+    #[test]
+    fn syntax_error() {
+        match parse(&String::from("BEGIN a := (2 END.")) {
+            Err(e) => assert_eq!(e.to_string(), "Invalid syntax at line 1, column 15: 'Z'"),
+            Ok(_) => panic!("parsed an unclosed parenthesis"),
+        }
+    }
+}
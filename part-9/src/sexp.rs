@@ -0,0 +1,95 @@
+use super::notation;
+use super::visit::Visitor;
+use super::{ASTArena, Assign, BinOp, Compound, NoOp, NodeId, Num, UnaryOp, Var};
+
+struct SExpression {
+    out: String,
+}
+
+impl SExpression {
+    fn expression(&mut self, ast: &ASTArena, id: NodeId) {
+        self.out += &notation::lisp(ast, id);
+    }
+}
+
+impl Visitor for SExpression {
+    type Result = ();
+
+    fn visit_binop(&mut self, ast: &ASTArena, id: NodeId, _node: &BinOp) {
+        self.expression(ast, id);
+    }
+
+    fn visit_num(&mut self, ast: &ASTArena, id: NodeId, _node: &Num) {
+        self.expression(ast, id);
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, id: NodeId, _node: &UnaryOp) {
+        self.expression(ast, id);
+    }
+
+    fn visit_compound(&mut self, ast: &ASTArena, _id: NodeId, node: &Compound) {
+        self.out += "(begin";
+        for child in &node.children {
+            self.out.push(' ');
+            self.visit(ast, *child);
+        }
+        self.out.push(')');
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) {
+        self.out += "(:= ";
+        self.visit(ast, node.left);
+        self.out.push(' ');
+        self.visit(ast, node.right);
+        self.out.push(')');
+    }
+
+    fn visit_var(&mut self, ast: &ASTArena, id: NodeId, _node: &Var) {
+        self.expression(ast, id);
+    }
+
+    fn visit_noop(&mut self, _ast: &ASTArena, _id: NodeId, _node: &NoOp) {
+        self.out += "(noop)";
+    }
+}
+
+pub fn dump(ast: &ASTArena, root: NodeId) -> String {
+    let mut sexp = SExpression {
+        out: String::new(),
+    };
+    sexp.visit(ast, root);
+    sexp.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dump;
+    use crate::Program;
+
+    fn sexp(text: &str) -> String {
+        let program = Program::parse(&text.to_string()).unwrap_or_else(|e| panic!("{}", e));
+        dump(&program.ast, program.root)
+    }
+
+    #[test]
+    fn empty_program() {
+        assert_eq!(sexp("BEGIN END."), "(begin (noop))");
+    }
+
+    #[test]
+    fn precedence_and_unary() {
+        assert_eq!(sexp("BEGIN a := 1 + 2 * 3; b := -a END."), "(begin (:= a (+ 1 (* 2 3))) (:= b (- a)))");
+        assert_eq!(sexp("BEGIN a := 10 - -2 END."), "(begin (:= a (- 10 (- 2))))");
+    }
+
+    #[test]
+    fn left_associative_operators() {
+        assert_eq!(sexp("BEGIN x := 1 - 2 - 3; y := 8 / 2 / +2 END."), "(begin (:= x (- (- 1 2) 3)) (:= y (/ (/ 8 2) (+ 2))))");
+        assert_eq!(sexp("BEGIN x := 1 - (2 - 3) END."), "(begin (:= x (- 1 (- 2 3))))");
+    }
+
+    #[test]
+    fn nested_blocks_and_empty_statements() {
+        assert_eq!(sexp("BEGIN BEGIN x := 1 END; ; BEGIN END END."), "(begin (begin (:= x 1)) (noop) (begin (noop)))");
+    }
+}
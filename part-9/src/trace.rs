@@ -0,0 +1,71 @@
+use std::fmt::Display;
+
+use super::Value;
+
+// one assignment as --trace prints it, written as soon as the statement has run
+pub struct TraceStep {
+    line: usize,
+    statement: String,
+    reads: Vec<(String, Value)>,
+    // None when the statement failed
+    write: Option<(String, Value)>,
+}
+
+impl TraceStep {
+    pub fn new(line: usize, statement: String, reads: Vec<(String, Value)>, write: Option<(String, Value)>) -> TraceStep {
+        TraceStep {
+            line,
+            statement,
+            reads,
+            write,
+        }
+    }
+}
+
+impl Display for TraceStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:>4}  {}", self.line, self.statement)?;
+        if !self.reads.is_empty() {
+            let reads: Vec<String> = self.reads.iter().map(|(name, value)| format!("{} = {}", name, value)).collect();
+            write!(f, "  [{}]", reads.join(", "))?;
+        }
+        match &self.write {
+            Some((name, value)) => write!(f, "  -> {} = {}", name, value),
+            None => write!(f, "  -> failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{Shared, output};
+    use crate::{Executable, Options, report};
+
+    fn trace(text: &str) -> String {
+        let mut options = Options::new();
+        options.trace = true;
+        let executable = Executable::load(&text.to_string(), &options).unwrap_or_else(|e| panic!("{}", e));
+        let shared = Shared::new();
+        let mut runtime = options.runtime();
+        runtime.trace = Some(Box::new(shared.clone()));
+        let result = executable.run(&mut runtime, &[]);
+        let mut text = shared.text();
+        text += &report(result, &mut runtime);
+        text
+    }
+
+    #[test]
+    fn statements_are_traced_as_written() {
+        assert_eq!(
+            trace("BEGIN a := 2 * 3 + 1;\nb := a - (4 - 4) END."),
+            "   1  a := 2 * 3 + 1  -> a = 7\n   2  b := a - (4 - 4)  [a = 7]  -> b = 7\na: 7\nb: 7\n",
+        );
+    }
+
+    #[test]
+    fn failing_statement_is_traced() {
+        let text = "BEGIN a := 5; b := a - 5; c := a / b; d := 1 END.";
+        let expected = "   1  a := 5  -> a = 5\n   1  b := a - 5  [a = 5]  -> b = 0\n   1  c := a / b  [a = 5, b = 0]  -> failed\n";
+        assert_eq!(trace(text), expected.to_string() + &output(text, &Options::new()));
+    }
+}
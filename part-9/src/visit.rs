@@ -0,0 +1,256 @@
+use super::{AST, ASTArena, Assign, BinOp, Compound, NoOp, NodeId, Num, UnaryOp, Var};
+
+pub trait VisitResult {
+    fn output() -> Self;
+    fn branch(&self) -> bool;
+}
+
+impl VisitResult for () {
+    fn output() -> Self {}
+
+    fn branch(&self) -> bool {
+        false
+    }
+}
+
+// a pass that fails with its own error type, e.g. an emitter's message
+impl<E> VisitResult for Result<(), E> {
+    fn output() -> Self {
+        Ok(())
+    }
+
+    fn branch(&self) -> bool {
+        self.is_err()
+    }
+}
+
+// returns early from a walk once a child's result asks to stop, e.g. a runtime error
+macro_rules! try_visit {
+    ($e:expr) => {
+        let result = $e;
+        if VisitResult::branch(&result) {
+            return result;
+        }
+    };
+}
+
+pub trait Visitor: Sized {
+    type Result: VisitResult;
+
+    fn visit(&mut self, ast: &ASTArena, id: NodeId) -> Self::Result {
+        match &ast[id] {
+            AST::BINOP(bin_op) => self.visit_binop(ast, id, bin_op),
+            AST::NUM(num) => self.visit_num(ast, id, num),
+            AST::UNARYOP(unary_op) => self.visit_unaryop(ast, id, unary_op),
+            AST::COMPOUND(compound) => self.visit_compound(ast, id, compound),
+            AST::ASSIGN(assign) => self.visit_assign(ast, id, assign),
+            AST::VAR(var) => self.visit_var(ast, id, var),
+            AST::NOOP(no_op) => self.visit_noop(ast, id, no_op),
+        }
+    }
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) -> Self::Result {
+        walk_binop(self, ast, node)
+    }
+
+    fn visit_num(&mut self, _ast: &ASTArena, _id: NodeId, _node: &Num) -> Self::Result {
+        Self::Result::output()
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) -> Self::Result {
+        walk_unaryop(self, ast, node)
+    }
+
+    fn visit_compound(&mut self, ast: &ASTArena, _id: NodeId, node: &Compound) -> Self::Result {
+        walk_compound(self, ast, node)
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) -> Self::Result {
+        walk_assign(self, ast, node)
+    }
+
+    fn visit_var(&mut self, _ast: &ASTArena, _id: NodeId, _node: &Var) -> Self::Result {
+        Self::Result::output()
+    }
+
+    fn visit_noop(&mut self, _ast: &ASTArena, _id: NodeId, _node: &NoOp) -> Self::Result {
+        Self::Result::output()
+    }
+}
+
+pub fn walk_binop<V: Visitor>(visitor: &mut V, ast: &ASTArena, node: &BinOp) -> V::Result {
+    try_visit!(visitor.visit(ast, node.left));
+    try_visit!(visitor.visit(ast, node.right));
+    V::Result::output()
+}
+
+pub fn walk_unaryop<V: Visitor>(visitor: &mut V, ast: &ASTArena, node: &UnaryOp) -> V::Result {
+    try_visit!(visitor.visit(ast, node.expr));
+    V::Result::output()
+}
+
+pub fn walk_compound<V: Visitor>(visitor: &mut V, ast: &ASTArena, node: &Compound) -> V::Result {
+    for child in &node.children {
+        try_visit!(visitor.visit(ast, *child));
+    }
+    V::Result::output()
+}
+
+pub fn walk_assign<V: Visitor>(visitor: &mut V, ast: &ASTArena, node: &Assign) -> V::Result {
+    try_visit!(visitor.visit(ast, node.left));
+    try_visit!(visitor.visit(ast, node.right));
+    V::Result::output()
+}
+
+pub trait VisitorMut: Sized {
+    type Result: VisitResult;
+
+    // the node stays in the arena, so each visit_*_mut gets its id and looks the node up itself
+    fn visit_mut(&mut self, ast: &mut ASTArena, id: NodeId) -> Self::Result {
+        match &ast[id] {
+            AST::BINOP(_) => self.visit_binop_mut(ast, id),
+            AST::NUM(_) => self.visit_num_mut(ast, id),
+            AST::UNARYOP(_) => self.visit_unaryop_mut(ast, id),
+            AST::COMPOUND(_) => self.visit_compound_mut(ast, id),
+            AST::ASSIGN(_) => self.visit_assign_mut(ast, id),
+            AST::VAR(_) => self.visit_var_mut(ast, id),
+            AST::NOOP(_) => self.visit_noop_mut(ast, id),
+        }
+    }
+
+    fn visit_binop_mut(&mut self, ast: &mut ASTArena, id: NodeId) -> Self::Result {
+        walk_binop_mut(self, ast, id)
+    }
+
+    fn visit_num_mut(&mut self, _ast: &mut ASTArena, _id: NodeId) -> Self::Result {
+        Self::Result::output()
+    }
+
+    fn visit_unaryop_mut(&mut self, ast: &mut ASTArena, id: NodeId) -> Self::Result {
+        walk_unaryop_mut(self, ast, id)
+    }
+
+    fn visit_compound_mut(&mut self, ast: &mut ASTArena, id: NodeId) -> Self::Result {
+        walk_compound_mut(self, ast, id)
+    }
+
+    fn visit_assign_mut(&mut self, ast: &mut ASTArena, id: NodeId) -> Self::Result {
+        walk_assign_mut(self, ast, id)
+    }
+
+    fn visit_var_mut(&mut self, _ast: &mut ASTArena, _id: NodeId) -> Self::Result {
+        Self::Result::output()
+    }
+
+    fn visit_noop_mut(&mut self, _ast: &mut ASTArena, _id: NodeId) -> Self::Result {
+        Self::Result::output()
+    }
+}
+
+pub fn walk_binop_mut<V: VisitorMut>(visitor: &mut V, ast: &mut ASTArena, id: NodeId) -> V::Result {
+    if let AST::BINOP(node) = &ast[id] {
+        let (left, right) = (node.left, node.right);
+        try_visit!(visitor.visit_mut(ast, left));
+        try_visit!(visitor.visit_mut(ast, right));
+    }
+    V::Result::output()
+}
+
+pub fn walk_unaryop_mut<V: VisitorMut>(visitor: &mut V, ast: &mut ASTArena, id: NodeId) -> V::Result {
+    if let AST::UNARYOP(node) = &ast[id] {
+        let expr = node.expr;
+        try_visit!(visitor.visit_mut(ast, expr));
+    }
+    V::Result::output()
+}
+
+pub fn walk_compound_mut<V: VisitorMut>(visitor: &mut V, ast: &mut ASTArena, id: NodeId) -> V::Result {
+    if let AST::COMPOUND(node) = &ast[id] {
+        for child in node.children.clone() {
+            try_visit!(visitor.visit_mut(ast, child));
+        }
+    }
+    V::Result::output()
+}
+
+pub fn walk_assign_mut<V: VisitorMut>(visitor: &mut V, ast: &mut ASTArena, id: NodeId) -> V::Result {
+    if let AST::ASSIGN(node) = &ast[id] {
+        let (left, right) = (node.left, node.right);
+        try_visit!(visitor.visit_mut(ast, left));
+        try_visit!(visitor.visit_mut(ast, right));
+    }
+    V::Result::output()
+}
+
+pub trait Fold: Sized {
+    // the node is moved out while its children are folded; each fold_* stores a node back under `id`
+    // or returns the id of the node that replaces it
+    fn fold(&mut self, ast: &mut ASTArena, id: NodeId) -> NodeId {
+        match std::mem::replace(&mut ast[id], AST::NOOP(NoOp::new())) {
+            AST::BINOP(bin_op) => self.fold_binop(ast, id, bin_op),
+            AST::NUM(num) => self.fold_num(ast, id, num),
+            AST::UNARYOP(unary_op) => self.fold_unaryop(ast, id, unary_op),
+            AST::COMPOUND(compound) => self.fold_compound(ast, id, compound),
+            AST::ASSIGN(assign) => self.fold_assign(ast, id, assign),
+            AST::VAR(var) => self.fold_var(ast, id, var),
+            AST::NOOP(no_op) => self.fold_noop(ast, id, no_op),
+        }
+    }
+
+    fn fold_binop(&mut self, ast: &mut ASTArena, id: NodeId, node: BinOp) -> NodeId {
+        ast[id] = AST::BINOP(walk_binop_fold(self, ast, node));
+        id
+    }
+
+    fn fold_num(&mut self, ast: &mut ASTArena, id: NodeId, node: Num) -> NodeId {
+        ast[id] = AST::NUM(node);
+        id
+    }
+
+    fn fold_unaryop(&mut self, ast: &mut ASTArena, id: NodeId, node: UnaryOp) -> NodeId {
+        ast[id] = AST::UNARYOP(walk_unaryop_fold(self, ast, node));
+        id
+    }
+
+    fn fold_compound(&mut self, ast: &mut ASTArena, id: NodeId, node: Compound) -> NodeId {
+        ast[id] = AST::COMPOUND(walk_compound_fold(self, ast, node));
+        id
+    }
+
+    fn fold_assign(&mut self, ast: &mut ASTArena, id: NodeId, node: Assign) -> NodeId {
+        ast[id] = AST::ASSIGN(walk_assign_fold(self, ast, node));
+        id
+    }
+
+    fn fold_var(&mut self, ast: &mut ASTArena, id: NodeId, node: Var) -> NodeId {
+        ast[id] = AST::VAR(node);
+        id
+    }
+
+    fn fold_noop(&mut self, ast: &mut ASTArena, id: NodeId, node: NoOp) -> NodeId {
+        ast[id] = AST::NOOP(node);
+        id
+    }
+}
+
+pub fn walk_binop_fold<F: Fold>(folder: &mut F, ast: &mut ASTArena, mut node: BinOp) -> BinOp {
+    node.left = folder.fold(ast, node.left);
+    node.right = folder.fold(ast, node.right);
+    node
+}
+
+pub fn walk_unaryop_fold<F: Fold>(folder: &mut F, ast: &mut ASTArena, mut node: UnaryOp) -> UnaryOp {
+    node.expr = folder.fold(ast, node.expr);
+    node
+}
+
+pub fn walk_compound_fold<F: Fold>(folder: &mut F, ast: &mut ASTArena, mut node: Compound) -> Compound {
+    node.children = node.children.into_iter().map(|child| folder.fold(ast, child)).collect();
+    node
+}
+
+pub fn walk_assign_fold<F: Fold>(folder: &mut F, ast: &mut ASTArena, mut node: Assign) -> Assign {
+    node.left = folder.fold(ast, node.left);
+    node.right = folder.fold(ast, node.right);
+    node
+}
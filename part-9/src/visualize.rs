@@ -0,0 +1,161 @@
+use super::visit::Visitor;
+use super::{ASTArena, Assign, BinOp, Compound, NoOp, NodeId, Num, Token, UnaryOp, Value, Var};
+
+struct ASTVisualizer {
+    ncount: usize,
+    depth: usize,
+    dot_body: Vec<String>,
+    outline: String,
+}
+
+impl ASTVisualizer {
+    fn new() -> ASTVisualizer {
+        ASTVisualizer {
+            ncount: 1,
+            depth: 0,
+            dot_body: Vec::new(),
+            outline: String::new(),
+        }
+    }
+
+    fn gendot(mut self, ast: &ASTArena, root: NodeId) -> String {
+        self.visit(ast, root);
+        let mut dot = String::from("digraph astgraph {\n");
+        dot += "  node [shape=circle, fontsize=12, fontname=\"Courier\", height=.1];\n";
+        dot += "  ranksep=.3;\n";
+        dot += "  edge [arrowsize=.5]\n\n";
+        dot += &self.dot_body.concat();
+        dot += "}\n";
+        dot
+    }
+
+    fn outline(mut self, ast: &ASTArena, root: NodeId) -> String {
+        self.visit(ast, root);
+        self.outline
+    }
+
+    fn node(&mut self, ast: &ASTArena, label: String, children: &[NodeId], interleave: bool) {
+        let num = self.ncount;
+        self.dot_body.push(format!("  node{} [label=\"{}\"]\n", num, label.replace('"', "\\\"")));
+        self.outline += &format!("{}{}\n", "  ".repeat(self.depth), label);
+        self.ncount += 1;
+
+        let mut edges = Vec::new();
+        self.depth += 1;
+        for child in children {
+            let edge = format!("  node{} -> node{}\n", num, self.ncount);
+            self.visit(ast, *child);
+            if interleave {
+                self.dot_body.push(edge);
+            } else {
+                edges.push(edge);
+            }
+        }
+        self.depth -= 1;
+        self.dot_body.append(&mut edges);
+    }
+}
+
+fn label(token: &Token) -> String {
+    token.value.clone().unwrap_or(Value::UNIT).to_string()
+}
+
+// like genastdot, compound statements are linked child by child and operators after all operands
+impl Visitor for ASTVisualizer {
+    type Result = ();
+
+    fn visit_binop(&mut self, ast: &ASTArena, _id: NodeId, node: &BinOp) {
+        self.node(ast, label(&node.op), &[node.left, node.right], false);
+    }
+
+    fn visit_num(&mut self, ast: &ASTArena, _id: NodeId, node: &Num) {
+        self.node(ast, label(&node.token), &[], false);
+    }
+
+    fn visit_unaryop(&mut self, ast: &ASTArena, _id: NodeId, node: &UnaryOp) {
+        self.node(ast, format!("unary {}", label(&node.op)), &[node.expr], false);
+    }
+
+    fn visit_compound(&mut self, ast: &ASTArena, _id: NodeId, node: &Compound) {
+        self.node(ast, String::from("Compound"), &node.children, true);
+    }
+
+    fn visit_assign(&mut self, ast: &ASTArena, _id: NodeId, node: &Assign) {
+        self.node(ast, label(&node.op), &[node.left, node.right], false);
+    }
+
+    fn visit_var(&mut self, ast: &ASTArena, _id: NodeId, node: &Var) {
+        self.node(ast, label(&node.token), &[], false);
+    }
+
+    fn visit_noop(&mut self, ast: &ASTArena, _id: NodeId, _node: &NoOp) {
+        self.node(ast, String::from("NoOp"), &[], false);
+    }
+}
+
+pub fn gendot(ast: &ASTArena, root: NodeId) -> String {
+    ASTVisualizer::new().gendot(ast, root)
+}
+
+pub fn outline(ast: &ASTArena, root: NodeId) -> String {
+    ASTVisualizer::new().outline(ast, root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gendot, outline};
+    use crate::Program;
+
+    fn program() -> Program {
+        Program::parse(&String::from("BEGIN a := -2 * (b + 1); END.")).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[test]
+    fn dot_graph() {
+        let program = program();
+        assert_eq!(gendot(&program.ast, program.root), "\
+digraph astgraph {
+  node [shape=circle, fontsize=12, fontname=\"Courier\", height=.1];
+  ranksep=.3;
+  edge [arrowsize=.5]
+
+  node1 [label=\"Compound\"]
+  node2 [label=\":=\"]
+  node3 [label=\"a\"]
+  node4 [label=\"*\"]
+  node5 [label=\"unary -\"]
+  node6 [label=\"2\"]
+  node5 -> node6
+  node7 [label=\"+\"]
+  node8 [label=\"b\"]
+  node9 [label=\"1\"]
+  node7 -> node8
+  node7 -> node9
+  node4 -> node5
+  node4 -> node7
+  node2 -> node3
+  node2 -> node4
+  node1 -> node2
+  node10 [label=\"NoOp\"]
+  node1 -> node10
+}
+");
+    }
+
+    #[test]
+    fn text_outline() {
+        let program = program();
+        assert_eq!(outline(&program.ast, program.root), "\
+Compound
+  :=
+    a
+    *
+      unary -
+        2
+      +
+        b
+        1
+  NoOp
+");
+    }
+}